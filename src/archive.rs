@@ -0,0 +1,128 @@
+use std::{fs, path::{Component, Path}};
+use tar::{Archive, Builder, EntryType, HeaderMode};
+use crate::CryptGuardError;
+
+/// Packs the tree below `dir` into a tar archive with paths relative to `dir`.
+/// Modes and modification times are kept, symlinks are stored as links instead of being followed.
+pub fn pack(dir: &Path) -> Result<Vec<u8>, CryptGuardError> {
+    let mut builder = Builder::new(Vec::new());
+    builder.mode(HeaderMode::Complete);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", dir)?;
+    Ok(builder.into_inner()?)
+}
+
+/// Unpacks an archive below `dest` and returns the number of entries.
+/// Absolute paths, `..` components and links pointing outside of `dest` are rejected.
+pub fn unpack(data: &[u8], dest: &Path) -> Result<usize, CryptGuardError> {
+    fs::create_dir_all(dest)?;
+
+    let mut archive = Archive::new(data);
+    archive.set_preserve_mtime(true);
+
+    let mut count = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        check_relative(&path)?;
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Directory => {},
+            EntryType::Symlink | EntryType::Link => {
+                let target = entry.link_name()?
+                    .ok_or_else(|| CryptGuardError::ParseError(format!("Archive link {} has no target", path.display())))?;
+                check_relative(&target)?;
+            },
+            other => return Err(CryptGuardError::ParseError(format!("Unsupported archive entry type {:?}: {}", other, path.display()))),
+        }
+
+        entry.unpack_in(dest)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn check_relative(path: &Path) -> Result<(), CryptGuardError> {
+    if path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        Ok(())
+    } else {
+        Err(CryptGuardError::ParseError(format!("Refusing archive entry outside of the target directory: {}", path.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use tar::Header;
+    use super::*;
+
+    /// An empty directory below the system temp dir, unique to this test and process.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crypt_guard-archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A one-entry archive with the name and link target written verbatim, bypassing the
+    /// checks `tar::Header::set_path` would apply.
+    fn raw_archive(name: &str, entry_type: EntryType, link: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+
+        let mut builder = Builder::new(Vec::new());
+        builder.append(&header, data).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn assert_rejected(name: &str, archive: &[u8]) {
+        let dir = scratch(name);
+        let dest = dir.join("dest");
+        assert!(matches!(unpack(archive, &dest), Err(CryptGuardError::ParseError(_))));
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = scratch("round-trip");
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(dir.join("src/top.txt"), b"top").unwrap();
+        fs::write(dir.join("src/nested/inner.txt"), b"inner").unwrap();
+
+        let count = unpack(&pack(&dir.join("src")).unwrap(), &dir.join("dest")).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(fs::read(dir.join("dest/top.txt")).unwrap(), b"top");
+        assert_eq!(fs::read(dir.join("dest/nested/inner.txt")).unwrap(), b"inner");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        assert_rejected("parent", &raw_archive("../escaped.txt", EntryType::Regular, "", b"x"));
+        assert_rejected("nested-parent", &raw_archive("sub/../../escaped.txt", EntryType::Regular, "", b"x"));
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        assert_rejected("absolute", &raw_archive("/tmp/crypt_guard-escaped.txt", EntryType::Regular, "", b"x"));
+    }
+
+    #[test]
+    fn rejects_escaping_links() {
+        assert_rejected("symlink-parent", &raw_archive("link", EntryType::Symlink, "../outside", b""));
+        assert_rejected("symlink-absolute", &raw_archive("link", EntryType::Symlink, "/etc/passwd", b""));
+        assert_rejected("hardlink-parent", &raw_archive("link", EntryType::Link, "../outside", b""));
+    }
+
+    #[test]
+    fn rejects_special_entries() {
+        assert_rejected("fifo", &raw_archive("fifo", EntryType::Fifo, "", b""));
+    }
+}
@@ -0,0 +1,267 @@
+use std::{borrow::Cow, io::{self, BufRead, BufReader, Cursor, Read, Write}};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::CryptGuardError;
+
+pub const ENCRYPTED_MESSAGE: &str = "ENCRYPTED MESSAGE";
+pub const PUBLIC_KEY: &str = "PUBLIC KEY";
+pub const SECRET_KEY: &str = "SECRET KEY";
+pub const SIGNATURE: &str = "SIGNATURE";
+pub const SIGNED_MESSAGE: &str = "SIGNED MESSAGE";
+pub const SIGNED_TEXT: &str = "SIGNED TEXT";
+
+const BEGIN: &str = "-----BEGIN CRYPT_GUARD ";
+const END: &str = "-----END CRYPT_GUARD ";
+const DASHES: &str = "-----";
+
+/// Raw bytes per Base64 line, which gives lines of 64 characters.
+const LINE_BYTES: usize = 48;
+
+/// OpenPGP CRC-24 parameters (RFC 4880, section 6.1).
+const CRC24_INIT: u32 = 0xB704CE;
+const CRC24_POLY: u32 = 0x1864CFB;
+
+pub fn is_armored(data: &[u8]) -> bool {
+    match data.iter().position(|byte| !byte.is_ascii_whitespace()) {
+        Some(start) => data[start..].starts_with(BEGIN.as_bytes()),
+        None => false,
+    }
+}
+
+/// The line that opens a block with `label`.
+pub fn begin_line(label: &str) -> String {
+    format!("{}{}{}", BEGIN, label, DASHES)
+}
+
+/// Armors `data` in one go.
+pub fn encode(label: &str, data: &[u8]) -> Vec<u8> {
+    let mut writer = ArmorWriter::new(Vec::new(), label).expect("writing to a Vec can't fail");
+    writer.write_all(data).expect("writing to a Vec can't fail");
+    writer.finish().expect("writing to a Vec can't fail")
+}
+
+/// Decodes armored text, checking the BEGIN/END labels and the CRC-24 checksum.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, CryptGuardError> {
+    let text = std::str::from_utf8(data).map_err(|_| invalid("not valid UTF-8"))?;
+    let mut lines = text.lines().map(str::trim).skip_while(|line| line.is_empty());
+
+    let label = lines.next()
+        .and_then(|line| line.strip_prefix(BEGIN))
+        .and_then(|line| line.strip_suffix(DASHES))
+        .ok_or_else(|| invalid("missing BEGIN line"))?;
+    let end = format!("{}{}{}", END, label, DASHES);
+
+    let mut body = String::new();
+    let mut checksum = None;
+    loop {
+        match lines.next() {
+            None => return Err(invalid(&format!("missing END line for {}", label))),
+            Some(line) if line == end => break,
+            Some(line) if line.starts_with('=') => checksum = Some(&line[1..]),
+            Some(line) => body.push_str(line),
+        }
+    }
+
+    let decoded = STANDARD.decode(&body).map_err(|e| invalid(&format!("invalid Base64: {}", e)))?;
+    let checksum = checksum.ok_or_else(|| invalid("missing checksum"))?;
+    let expected = STANDARD.decode(checksum).map_err(|e| invalid(&format!("invalid checksum: {}", e)))?;
+    if expected != crc24(CRC24_INIT, &decoded).to_be_bytes()[1..] {
+        return Err(invalid("checksum mismatch, the text was damaged"));
+    }
+    Ok(decoded)
+}
+
+/// Decodes `data` if it is armored and passes binary data through unchanged.
+pub fn dearmor(data: &[u8]) -> Result<Cow<'_, [u8]>, CryptGuardError> {
+    match is_armored(data) {
+        true => Ok(Cow::Owned(decode(data)?)),
+        false => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Wraps a container reader so that armored input is decoded transparently.
+/// Binary containers start with their magic bytes, so a leading `-` or whitespace means armor;
+/// armored input is decoded in memory, binary input keeps streaming.
+pub fn reader<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>, CryptGuardError> {
+    let mut reader = BufReader::new(reader);
+    match reader.fill_buf()?.first() {
+        Some(byte) if *byte == b'-' || byte.is_ascii_whitespace() => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            Ok(Box::new(Cursor::new(decode(&data)?)))
+        },
+        _ => Ok(Box::new(reader)),
+    }
+}
+
+/// Armors everything written to it; `finish` writes the checksum and END line.
+pub struct ArmorWriter<W: Write> {
+    inner: W,
+    label: String,
+    pending: Vec<u8>,
+    crc: u32,
+}
+
+impl<W: Write> ArmorWriter<W> {
+    pub fn new(mut inner: W, label: &str) -> io::Result<Self> {
+        writeln!(inner, "{}{}{}", BEGIN, label, DASHES)?;
+        Ok(ArmorWriter { inner, label: label.to_string(), pending: Vec::new(), crc: CRC24_INIT })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            writeln!(self.inner, "{}", STANDARD.encode(&self.pending))?;
+        }
+        writeln!(self.inner, "={}", STANDARD.encode(&self.crc.to_be_bytes()[1..]))?;
+        writeln!(self.inner, "{}{}{}", END, self.label, DASHES)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ArmorWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc = crc24(self.crc, buf);
+        self.pending.extend_from_slice(buf);
+
+        let full = self.pending.len() / LINE_BYTES * LINE_BYTES;
+        for line in self.pending[..full].chunks(LINE_BYTES) {
+            writeln!(self.inner, "{}", STANDARD.encode(line))?;
+        }
+        self.pending.drain(..full);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bare text encodings for passing ciphertext inline, without BEGIN/END lines or checksum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    pub fn from_str(input: &str) -> Result<Self, CryptGuardError> {
+        match input.to_lowercase().as_str() {
+            "hex" => Ok(Encoding::Hex),
+            "base64" => Ok(Encoding::Base64),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid encoding: {} (expected hex or base64)", input))),
+        }
+    }
+
+    /// Encodes `data` on a single line.
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Hex => hex::encode(data),
+            Encoding::Base64 => STANDARD.encode(data),
+        }
+    }
+}
+
+/// Decodes inline text that is armored, hex or Base64, whichever of them yields data starting
+/// with `magic`. Whitespace, such as line breaks from copying, is ignored.
+pub fn decode_inline(text: &str, magic: &[u8]) -> Result<Vec<u8>, CryptGuardError> {
+    if is_armored(text.as_bytes()) {
+        return decode(text.as_bytes());
+    }
+
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    [hex::decode(&compact).ok(), STANDARD.decode(&compact).ok()]
+        .into_iter()
+        .flatten()
+        .find(|data| data.starts_with(magic))
+        .ok_or_else(|| CryptGuardError::ParseError("The message is neither armored nor hex or Base64 encoded crypt_guard data".to_string()))
+}
+
+fn crc24(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+fn invalid(reason: &str) -> CryptGuardError {
+    CryptGuardError::ParseError(format!("Invalid armor: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    /// Replaces the first character of the first Base64 line with another valid one.
+    fn damage(armored: &[u8]) -> String {
+        let text = String::from_utf8(armored.to_vec()).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let replacement = if lines[1].starts_with('A') { "B" } else { "A" };
+        lines[1].replace_range(..1, replacement);
+        lines.join("\n")
+    }
+
+    #[test]
+    fn crc24_matches_openpgp_check_value() {
+        assert_eq!(crc24(CRC24_INIT, b"123456789"), 0x21CF02);
+        assert_eq!(crc24(crc24(CRC24_INIT, b"1234"), b"56789"), 0x21CF02);
+    }
+
+    #[test]
+    fn round_trips_around_line_boundaries() {
+        for len in [0, 1, LINE_BYTES - 1, LINE_BYTES, LINE_BYTES + 1, 5 * LINE_BYTES + 3] {
+            let data = sample(len);
+            let armored = encode(SIGNATURE, &data);
+            assert!(is_armored(&armored));
+            assert!(String::from_utf8(armored.clone()).unwrap().lines().all(|line| line.len() <= 64));
+            assert_eq!(decode(&armored).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn writer_output_does_not_depend_on_write_sizes() {
+        let data = sample(3 * LINE_BYTES + 10);
+        let mut writer = ArmorWriter::new(Vec::new(), ENCRYPTED_MESSAGE).unwrap();
+        for piece in data.chunks(5) {
+            writer.write_all(piece).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), encode(ENCRYPTED_MESSAGE, &data));
+    }
+
+    #[test]
+    fn decodes_crlf_and_surrounding_whitespace() {
+        let data = sample(100);
+        let armored = String::from_utf8(encode(PUBLIC_KEY, &data)).unwrap().replace('\n', "\r\n");
+        assert_eq!(decode(format!("\n  {}\n", armored).as_bytes()).unwrap(), data);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let armored = encode(SECRET_KEY, &sample(100));
+        assert!(matches!(decode(damage(&armored).as_bytes()), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_missing_checksum_and_mismatched_end() {
+        let text = String::from_utf8(encode(SIGNATURE, &sample(10))).unwrap();
+        let without_checksum: Vec<&str> = text.lines().filter(|line| !line.starts_with('=')).collect();
+        assert!(decode(without_checksum.join("\n").as_bytes()).is_err());
+        assert!(decode(text.replace("END CRYPT_GUARD SIGNATURE", "END CRYPT_GUARD PUBLIC KEY").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn dearmor_passes_binary_through() {
+        let data = b"CRYPTGRD\x05binary";
+        assert!(matches!(dearmor(data).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(dearmor(&encode(ENCRYPTED_MESSAGE, data)).unwrap().as_ref(), data);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{armor, CryptGuardError};
+
+/// Normalizes text the way it is signed: the final line ending is dropped, trailing spaces and
+/// tabs are dropped from every line, and the remaining lines are separated by `\n`.
+pub fn normalize(text: &str) -> String {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.split('\n').map(trim_line).collect::<Vec<_>>().join("\n")
+}
+
+/// The bytes a cleartext signature covers: normalized text with `\r\n` line endings, so the
+/// signature survives transfer between systems with different line endings.
+pub fn canonicalize(normalized: &str) -> Vec<u8> {
+    normalized.replace('\n', "\r\n").into_bytes()
+}
+
+fn trim_line(line: &str) -> &str {
+    line.trim_end_matches([' ', '\t', '\r'])
+}
+
+pub fn is_cleartext(data: &[u8]) -> bool {
+    let begin = armor::begin_line(armor::SIGNED_TEXT);
+    std::str::from_utf8(data).is_ok_and(|text| text.lines().map(str::trim_end).find(|line| !line.is_empty()) == Some(begin.as_str()))
+}
+
+/// Writes the already normalized `text` followed by the armored `signature`. Lines starting with
+/// `-` are dash-escaped with `- `, so no line of the text can be taken for the signature block.
+pub fn encode(text: &str, signature: &[u8]) -> Vec<u8> {
+    let mut out = armor::begin_line(armor::SIGNED_TEXT);
+    out.push('\n');
+    for line in text.split('\n') {
+        if line.starts_with('-') {
+            out.push_str("- ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    let mut out = out.into_bytes();
+    out.extend_from_slice(&armor::encode(armor::SIGNATURE, signature));
+    out
+}
+
+/// Splits a cleartext-signed message into its normalized text and its signature. The text is
+/// normalized line by line again, so line endings changed in transit don't matter.
+pub fn decode(data: &[u8]) -> Result<(String, Vec<u8>), CryptGuardError> {
+    let input = std::str::from_utf8(data).map_err(|_| invalid("not valid UTF-8"))?;
+    let begin = armor::begin_line(armor::SIGNED_TEXT);
+    let signature_begin = armor::begin_line(armor::SIGNATURE);
+
+    let mut lines = input.lines().skip_while(|line| line.trim().is_empty());
+    if lines.next().map(str::trim_end) != Some(begin.as_str()) {
+        return Err(invalid("missing BEGIN line"));
+    }
+
+    let mut text = Vec::new();
+    let mut signature = None;
+    for line in lines.by_ref() {
+        if line.trim_end() == signature_begin {
+            signature = Some(line);
+            break;
+        }
+        match line.strip_prefix('-') {
+            Some(escaped) => text.push(trim_line(escaped.strip_prefix(' ').ok_or_else(|| invalid("a text line starts with an unescaped dash"))?)),
+            None => text.push(trim_line(line)),
+        }
+    }
+
+    let signature = signature.ok_or_else(|| invalid("missing signature block"))?;
+    let block: Vec<&str> = std::iter::once(signature).chain(lines).collect();
+    Ok((text.join("\n"), armor::decode(block.join("\n").as_bytes())?))
+}
+
+fn invalid(reason: &str) -> CryptGuardError {
+    CryptGuardError::ParseError(format!("Invalid cleartext signature: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNATURE: &[u8] = b"not a real signature";
+
+    /// Signs the way `sign --clearsign` does and returns what `verify cleartext` recovers.
+    fn round_trip(text: &str) -> String {
+        let normalized = normalize(text);
+        let (decoded, signature) = decode(&encode(&normalized, SIGNATURE)).unwrap();
+        assert_eq!(decoded, normalized);
+        assert_eq!(signature, SIGNATURE);
+        decoded
+    }
+
+    #[test]
+    fn normalizes_line_endings_and_trailing_whitespace() {
+        assert_eq!(normalize("a \t\r\nb\r\n"), "a\nb");
+        assert_eq!(normalize("a\nb"), "a\nb");
+        assert_eq!(canonicalize("a\nb"), b"a\r\nb");
+    }
+
+    #[test]
+    fn round_trips_trailing_blank_lines() {
+        assert_eq!(round_trip("a\n\n"), "a\n");
+        assert_eq!(round_trip("a\n\n\n"), "a\n\n");
+        assert_eq!(round_trip("\n"), "");
+    }
+
+    #[test]
+    fn round_trips_crlf_input() {
+        assert_eq!(round_trip("first\r\nsecond \r\n\r\n"), "first\nsecond\n");
+    }
+
+    #[test]
+    fn round_trips_dash_escaped_lines() {
+        assert_eq!(round_trip("-leading dash\n-----BEGIN CRYPT_GUARD SIGNATURE-----\n"), "-leading dash\n-----BEGIN CRYPT_GUARD SIGNATURE-----");
+    }
+
+    #[test]
+    fn survives_crlf_conversion_in_transit() {
+        let normalized = normalize("a\n\nb\n");
+        let converted = String::from_utf8(encode(&normalized, SIGNATURE)).unwrap().replace('\n', "\r\n");
+        assert_eq!(decode(converted.as_bytes()).unwrap(), (normalized, SIGNATURE.to_vec()));
+    }
+
+    #[test]
+    fn rejects_unescaped_dash() {
+        let encoded = String::from_utf8(encode("text", SIGNATURE)).unwrap();
+        let tampered = encoded.replacen("text", "-text", 1);
+        assert!(matches!(decode(tampered.as_bytes()), Err(CryptGuardError::ParseError(_))));
+    }
+}
@@ -0,0 +1,103 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use crate::CryptGuardError;
+
+/// Length of Ed25519 public keys and secret key seeds.
+const ED25519_KEY_LEN: usize = 32;
+/// Length of Ed25519 signatures.
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// Prefix of the message both halves of a composite signature sign, so neither half
+/// verifies as a plain signature over the same data.
+const DOMAIN: &[u8] = b"crypt_guard composite signature v1\0";
+
+/// Prepends a fresh Ed25519 key pair to a Falcon or Dilithium key pair, giving the
+/// `ed25519 || post-quantum` layout of composite public and secret keys.
+pub fn keypair(pq_public: Vec<u8>, pq_secret: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    let mut seed = [0u8; ED25519_KEY_LEN];
+    OsRng.fill_bytes(&mut seed);
+    let signing_key = SigningKey::from_bytes(&seed);
+    ([signing_key.verifying_key().as_bytes().as_slice(), &pq_public].concat(), [seed.as_slice(), &pq_secret].concat())
+}
+
+/// Splits a composite key into its Ed25519 and post-quantum parts.
+pub fn split_key(key: &[u8]) -> Result<([u8; ED25519_KEY_LEN], &[u8]), CryptGuardError> {
+    match key.split_first_chunk::<ED25519_KEY_LEN>() {
+        Some((ed25519, pq)) => Ok((*ed25519, pq)),
+        None => Err(CryptGuardError::ParseError("Composite key is too short".to_string())),
+    }
+}
+
+/// Splits a composite signature into its Ed25519 and post-quantum parts.
+pub fn split_signature(signature: &[u8]) -> Result<([u8; ED25519_SIGNATURE_LEN], &[u8]), CryptGuardError> {
+    match signature.split_first_chunk::<ED25519_SIGNATURE_LEN>() {
+        Some((ed25519, pq)) => Ok((*ed25519, pq)),
+        None => Err(CryptGuardError::SignatureError("Composite signature is too short".to_string())),
+    }
+}
+
+/// Length of a composite signature whose post-quantum half is `pq_len` bytes.
+pub fn signature_len(pq_len: usize) -> usize {
+    ED25519_SIGNATURE_LEN + pq_len
+}
+
+/// The domain-separated message both algorithms sign.
+pub fn message(data: &[u8]) -> Vec<u8> {
+    [DOMAIN, data].concat()
+}
+
+/// Strips the domain prefix from a message recovered from composite signed data.
+pub fn strip_message(message: &[u8]) -> Result<&[u8], CryptGuardError> {
+    message.strip_prefix(DOMAIN)
+        .ok_or_else(|| CryptGuardError::SignatureError("Signed data is not a composite signature".to_string()))
+}
+
+pub fn sign_ed25519(seed: &[u8; ED25519_KEY_LEN], message: &[u8]) -> [u8; ED25519_SIGNATURE_LEN] {
+    SigningKey::from_bytes(seed).sign(message).to_bytes()
+}
+
+/// Strict Ed25519 verification; a malformed public key counts as a failed check.
+pub fn verify_ed25519(public_key: &[u8; ED25519_KEY_LEN], message: &[u8], signature: &[u8; ED25519_SIGNATURE_LEN]) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key.verify_strict(message, &Signature::from_bytes(signature)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keypair_layout() {
+        let (public, secret) = keypair(vec![1; 10], vec![2; 20]);
+        let (ed25519_public, pq_public) = split_key(&public).unwrap();
+        let (seed, pq_secret) = split_key(&secret).unwrap();
+        assert_eq!((pq_public, pq_secret), (&[1; 10][..], &[2; 20][..]));
+        assert_eq!(SigningKey::from_bytes(&seed).verifying_key().as_bytes(), &ed25519_public);
+        assert!(split_key(&[0; ED25519_KEY_LEN - 1]).is_err());
+        assert!(split_signature(&[0; ED25519_SIGNATURE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn ed25519_round_trip() {
+        let (public, secret) = keypair(Vec::new(), Vec::new());
+        let (public, _) = split_key(&public).unwrap();
+        let (seed, _) = split_key(&secret).unwrap();
+
+        let message = message(b"data");
+        let mut signature = sign_ed25519(&seed, &message);
+        assert!(verify_ed25519(&public, &message, &signature));
+        assert!(!verify_ed25519(&public, &message[1..], &signature));
+        signature[0] ^= 1;
+        assert!(!verify_ed25519(&public, &message, &signature));
+    }
+
+    #[test]
+    fn message_is_domain_separated() {
+        let message = message(b"data");
+        assert_ne!(message, b"data");
+        assert_eq!(strip_message(&message).unwrap(), b"data");
+        assert!(strip_message(b"data").is_err());
+    }
+}
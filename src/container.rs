@@ -0,0 +1,262 @@
+use std::io::{Read, Write};
+use crate::{kdf::Kdf, kem::Kem, recipient::Stanza, CryptGuardError, KeyTypes, SymmetricAlgorithm};
+
+/// Magic bytes at the start of every container produced by `encrypt`.
+pub const MAGIC: &[u8; 8] = b"CRYPTGRD";
+/// Current container format version. Version 1 containers have no recipient stanzas,
+/// versions 1 and 2 no embedded signature, versions before 4 no content flags and
+/// versions before 5 only plain Kyber.
+pub const VERSION: u8 = 5;
+
+/// Content flag: the plaintext is a tar archive of a directory.
+const FLAG_ARCHIVE: u8 = 0x01;
+/// Content flag: `cipher` is a hybrid X25519 + Kyber ciphertext.
+const FLAG_HYBRID: u8 = 0x02;
+/// Content flag: `cipher` is a FIPS 203 ML-KEM ciphertext.
+const FLAG_ML_KEM: u8 = 0x04;
+
+/// The sender's signature over the SHA3-512 digest of the plaintext, added by `encrypt --sign-with`.
+#[derive(Debug)]
+pub struct EmbeddedSignature {
+    pub key_type: KeyTypes,
+    pub signature: Vec<u8>,
+}
+
+/// Upper bound on a KEM ciphertext length field, far above the 1600 bytes of a hybrid
+/// X25519 + Kyber1024 ciphertext, so a damaged header can't make us allocate gigabytes.
+pub(crate) const MAX_CIPHER_LEN: usize = 1 << 16;
+/// Upper bound on a signature length field, far above the largest signature of any supported
+/// scheme, for the same reason.
+pub(crate) const MAX_SIGNATURE_LEN: usize = 1 << 20;
+
+/// Everything `decrypt` needs besides the secret key and the passphrase.
+#[derive(Debug)]
+pub struct Header {
+    pub algorithm: SymmetricAlgorithm,
+    pub key_size: usize,
+    pub kdf: Kdf,
+    pub cipher: Vec<u8>,
+    /// Full nonce for single-shot payloads, STREAM nonce prefix for chunked ones.
+    pub nonce: Option<String>,
+    /// Plaintext bytes per chunk, or 0 if the payload was encrypted in one piece.
+    pub chunk_size: u32,
+    /// Data-encryption key wrapped once per recipient; empty when `cipher` holds a single
+    /// Kyber ciphertext, otherwise `cipher` is empty and `key_size` is 0.
+    pub recipients: Vec<Stanza>,
+    pub signature: Option<EmbeddedSignature>,
+    /// The plaintext is a tar archive that `decrypt` unpacks into a directory.
+    pub archive: bool,
+    /// The KEM that produced `cipher`.
+    pub kem: Kem,
+}
+
+impl Header {
+    pub fn is_chunked(&self) -> bool {
+        self.chunk_size != 0
+    }
+
+    pub fn has_recipients(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Canonical encoding of the header; chunked payloads use it as associated data.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CryptGuardError> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(self.algorithm.id());
+        buf.extend_from_slice(&(self.key_size as u16).to_be_bytes());
+
+        self.kdf.encode(&mut buf);
+
+        buf.extend_from_slice(&self.chunk_size.to_be_bytes());
+        buf.extend_from_slice(&(self.cipher.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.cipher);
+
+        let nonce = match &self.nonce {
+            Some(nonce) => hex::decode(nonce)
+                .map_err(|e| CryptGuardError::ParseError(format!("Invalid nonce: {}", e)))?,
+            None => Vec::new(),
+        };
+        buf.push(nonce.len() as u8);
+        buf.extend_from_slice(&nonce);
+
+        buf.push(self.recipients.len() as u8);
+        for stanza in &self.recipients {
+            stanza.encode(&mut buf);
+        }
+
+        match &self.signature {
+            Some(embedded) => {
+                buf.push(embedded.key_type.id());
+                buf.extend_from_slice(&(embedded.signature.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&embedded.signature);
+            },
+            None => buf.push(0),
+        }
+
+        let mut flags = 0;
+        if self.archive {
+            flags |= FLAG_ARCHIVE;
+        }
+        match self.kem {
+            Kem::Kyber => {},
+            Kem::X25519Kyber => flags |= FLAG_HYBRID,
+            Kem::MlKem => flags |= FLAG_ML_KEM,
+        }
+        buf.push(flags);
+        Ok(buf)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), CryptGuardError> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, CryptGuardError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CryptGuardError::ParseError("Input is not a crypt_guard container".to_string()));
+        }
+
+        let version = read_u8(reader)?;
+        if version == 0 || version > VERSION {
+            return Err(CryptGuardError::ParseError(format!("Unsupported container version: {}", version)));
+        }
+
+        let algorithm = SymmetricAlgorithm::from_id(read_u8(reader)?)?;
+        let key_size = read_u16(reader)? as usize;
+
+        let kdf = Kdf::decode(reader)?;
+
+        let chunk_size = read_u32(reader)?;
+        let cipher_len = read_u32(reader)? as usize;
+        if cipher_len > MAX_CIPHER_LEN {
+            return Err(CryptGuardError::ParseError(format!("Container is corrupted: a {} byte KEM ciphertext is implausible", cipher_len)));
+        }
+        let cipher = read_vec(reader, cipher_len)?;
+
+        let nonce_len = read_u8(reader)? as usize;
+        let nonce = match nonce_len {
+            0 => None,
+            len => Some(hex::encode(read_vec(reader, len)?)),
+        };
+
+        let recipient_count = if version == 1 { 0 } else { read_u8(reader)? };
+        let recipients = (0..recipient_count)
+            .map(|_| Stanza::decode(reader, version))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let signature = match if version < 3 { 0 } else { read_u8(reader)? } {
+            0 => None,
+            id => {
+                let key_type = KeyTypes::from_id(id)?;
+                let signature_len = read_u32(reader)? as usize;
+                if signature_len > MAX_SIGNATURE_LEN {
+                    return Err(CryptGuardError::ParseError(format!("Container is corrupted: a {} byte signature is implausible", signature_len)));
+                }
+                Some(EmbeddedSignature { key_type, signature: read_vec(reader, signature_len)? })
+            },
+        };
+
+        let flags = if version < 4 { 0 } else { read_u8(reader)? };
+        let known = if version < 5 { FLAG_ARCHIVE } else { FLAG_ARCHIVE | FLAG_HYBRID | FLAG_ML_KEM };
+        if flags & !known != 0 {
+            return Err(CryptGuardError::ParseError(format!("Unknown container flags: {:#04x}", flags)));
+        }
+        let kem = match (flags & FLAG_HYBRID != 0, flags & FLAG_ML_KEM != 0) {
+            (false, false) => Kem::Kyber,
+            (true, false) => Kem::X25519Kyber,
+            (false, true) => Kem::MlKem,
+            (true, true) => return Err(CryptGuardError::ParseError(format!("Conflicting container flags: {:#04x}", flags))),
+        };
+
+        Ok(Header {
+            algorithm, key_size, kdf, cipher, nonce, chunk_size, recipients, signature,
+            archive: flags & FLAG_ARCHIVE != 0,
+            kem,
+        })
+    }
+}
+
+/// Writes the header followed by the encrypted payload.
+pub fn write_container<W: Write>(writer: &mut W, header: &Header, payload: &[u8]) -> Result<(), CryptGuardError> {
+    header.write_to(writer)?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut R) -> Result<u8, CryptGuardError> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u16<R: Read>(reader: &mut R) -> Result<u16, CryptGuardError> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Result<u32, CryptGuardError> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+pub(crate) fn read_vec<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, CryptGuardError> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(cipher: Vec<u8>) -> Header {
+        Header { algorithm: SymmetricAlgorithm::XChaCha20Poly1305, key_size: 1024, kdf: Kdf::argon2id(), cipher, nonce: Some("00".repeat(19)), chunk_size: 65536, recipients: Vec::new(), signature: None, archive: false, kem: Kem::Kyber }
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = header(vec![1; 1568]);
+        let mut encoded = Vec::new();
+        write_container(&mut encoded, &header, b"payload").unwrap();
+
+        let mut reader = encoded.as_slice();
+        let decoded = Header::read_from(&mut reader).unwrap();
+        assert_eq!(decoded.algorithm, header.algorithm);
+        assert_eq!(decoded.key_size, header.key_size);
+        assert_eq!(decoded.kdf, header.kdf);
+        assert_eq!(decoded.cipher, header.cipher);
+        assert_eq!(decoded.nonce, header.nonce);
+        assert_eq!(decoded.chunk_size, header.chunk_size);
+        assert_eq!(reader, b"payload");
+    }
+
+    #[test]
+    fn rejects_oversized_cipher() {
+        let mut encoded = Vec::new();
+        header(vec![0; MAX_CIPHER_LEN + 1]).write_to(&mut encoded).unwrap();
+        assert!(matches!(Header::read_from(&mut encoded.as_slice()), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_oversized_signature() {
+        let mut header = header(vec![1; 1568]);
+        header.signature = Some(EmbeddedSignature { key_type: KeyTypes::Falcon1024, signature: vec![0; MAX_SIGNATURE_LEN + 1] });
+        let mut encoded = Vec::new();
+        header.write_to(&mut encoded).unwrap();
+        assert!(matches!(Header::read_from(&mut encoded.as_slice()), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let mut encoded = Vec::new();
+        header(vec![1; 1568]).write_to(&mut encoded).unwrap();
+        encoded.truncate(encoded.len() - 10);
+        assert!(matches!(Header::read_from(&mut encoded.as_slice()), Err(CryptGuardError::IoError(_))));
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::{self, Read, Write};
+use sha2::Sha256;
+use sha3::{Digest, Sha3_512};
+
+/// SHA3-512 of everything `reader` yields, read in fixed-size blocks.
+pub fn sha3_512<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    hash::<Sha3_512, R>(reader)
+}
+
+/// SHA-256 of everything `reader` yields, as used by `SHA256SUMS` manifests.
+pub fn sha256<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    hash::<Sha256, R>(reader)
+}
+
+fn hash<D: Digest, R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Passes writes through to `inner` while hashing them with SHA3-512.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha3_512,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter { inner, hasher: Sha3_512::new() }
+    }
+
+    /// Returns the digest of everything written so far.
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize().to_vec()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
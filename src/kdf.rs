@@ -0,0 +1,96 @@
+use std::io::Read;
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{rngs::OsRng, RngCore};
+use crate::{container::{read_u32, read_u8, read_vec}, CryptGuardError};
+
+const KDF_NONE: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+
+/// Ceilings on the Argon2id costs read from a header, far above the defaults (19 MiB, 2 passes,
+/// 1 lane), so a damaged or hostile file can't make decryption take gigabytes or hours.
+const MAX_M_COST: u32 = 2 * 1024 * 1024;
+const MAX_T_COST: u32 = 64;
+const MAX_P_COST: u32 = 16;
+
+/// Passphrase stretching parameters, stored next to whatever they protect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kdf {
+    None,
+    Argon2id {
+        salt: Vec<u8>,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+impl Kdf {
+    /// Fresh Argon2id parameters with a random salt.
+    pub fn argon2id() -> Self {
+        let mut salt = vec![0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Kdf::Argon2id {
+            salt,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    /// Stretches the passphrase into a 32 byte key.
+    pub fn derive_key(&self, passphrase: &str) -> Result<[u8; 32], CryptGuardError> {
+        match self {
+            Kdf::None => Err(CryptGuardError::ParseError("No key derivation parameters present".to_string())),
+            Kdf::Argon2id { salt, m_cost, t_cost, p_cost } => {
+                let params = Params::new(*m_cost, *t_cost, *p_cost, Some(32))
+                    .map_err(|e| CryptGuardError::ParseError(format!("Invalid KDF parameters: {}", e)))?;
+                let mut derived = [0u8; 32];
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut derived)
+                    .map_err(|e| CryptGuardError::ParseError(format!("Key derivation failed: {}", e)))?;
+                Ok(derived)
+            }
+        }
+    }
+
+    /// Stretches the passphrase, returning the hex string used as the crypt_guard passphrase.
+    pub fn derive(&self, passphrase: &str) -> Result<String, CryptGuardError> {
+        match self {
+            Kdf::None => Ok(passphrase.to_string()),
+            Kdf::Argon2id { .. } => Ok(hex::encode(self.derive_key(passphrase)?)),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Kdf::None => buf.push(KDF_NONE),
+            Kdf::Argon2id { salt, m_cost, t_cost, p_cost } => {
+                buf.push(KDF_ARGON2ID);
+                buf.push(salt.len() as u8);
+                buf.extend_from_slice(salt);
+                buf.extend_from_slice(&m_cost.to_be_bytes());
+                buf.extend_from_slice(&t_cost.to_be_bytes());
+                buf.extend_from_slice(&p_cost.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Self, CryptGuardError> {
+        match read_u8(reader)? {
+            KDF_NONE => Ok(Kdf::None),
+            KDF_ARGON2ID => {
+                let salt_len = read_u8(reader)? as usize;
+                let salt = read_vec(reader, salt_len)?;
+                let (m_cost, t_cost, p_cost) = (read_u32(reader)?, read_u32(reader)?, read_u32(reader)?);
+                if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+                    return Err(CryptGuardError::ParseError(format!(
+                        "KDF parameters are implausible: m_cost {} KiB, t_cost {}, p_cost {} (at most {}, {} and {})",
+                        m_cost, t_cost, p_cost, MAX_M_COST, MAX_T_COST, MAX_P_COST
+                    )));
+                }
+                Ok(Kdf::Argon2id { salt, m_cost, t_cost, p_cost })
+            }
+            id => Err(CryptGuardError::ParseError(format!("Unknown KDF identifier: {}", id))),
+        }
+    }
+}
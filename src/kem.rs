@@ -0,0 +1,277 @@
+use fips203::traits::{Decaps, Encaps, KeyGen, SerDes};
+use rand::rngs::OsRng;
+use sha3::{Digest, Sha3_256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use ::crypt_guard::{*, error::*};
+
+/// Length of X25519 public keys, secret keys and shared secrets.
+const X25519_LEN: usize = 32;
+
+/// The key encapsulation mechanism behind a container's or recipient stanza's ciphertext.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Kem {
+    /// CRYSTALS-Kyber round 3, the pre-standard encoding crypt_guard implements.
+    Kyber,
+    /// X25519 combined with Kyber, see `hybrid_encapsulate`.
+    X25519Kyber,
+    /// FIPS 203 ML-KEM.
+    MlKem,
+}
+
+impl Kem {
+    pub fn id(&self) -> u8 {
+        match self {
+            Kem::Kyber => 0,
+            Kem::X25519Kyber => 1,
+            Kem::MlKem => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Kem::Kyber),
+            1 => Some(Kem::X25519Kyber),
+            2 => Some(Kem::MlKem),
+            _ => None,
+        }
+    }
+
+    /// Key type name for a parameter set, e.g. `kyber768` or `ml-kem-768`.
+    pub fn name(&self, key_size: usize) -> String {
+        match self {
+            Kem::Kyber => format!("kyber{}", key_size),
+            Kem::X25519Kyber => format!("x25519kyber{}", key_size),
+            Kem::MlKem => format!("ml-kem-{}", key_size),
+        }
+    }
+}
+
+/// Encapsulates a fresh shared secret to a Kyber public key, returning `(shared_secret, ciphertext)`.
+pub fn encapsulate(key_size: usize, public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    match key_size {
+        1024 => KeyControKyber1024::encap(public_key),
+        768 => KeyControKyber768::encap(public_key),
+        512 => KeyControKyber512::encap(public_key),
+        _ => Err(CryptError::new("Invalid Kyber key size")),
+    }
+}
+
+/// Recovers the shared secret from a KEM ciphertext with the Kyber secret key.
+pub fn decapsulate(key_size: usize, secret_key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptError> {
+    match key_size {
+        1024 => KeyControKyber1024::decap(secret_key, ciphertext),
+        768 => KeyControKyber768::decap(secret_key, ciphertext),
+        512 => KeyControKyber512::decap(secret_key, ciphertext),
+        _ => Err(CryptError::new("Invalid Kyber key size")),
+    }
+}
+
+/// The public key embedded in a Kyber secret key, which is laid out as
+/// `indcpa_sk (384·k) || pk (384·k + 32) || H(pk) || z`. FIPS 203 kept this layout for ML-KEM.
+pub fn public_from_secret(key_size: usize, secret_key: &[u8]) -> Option<&[u8]> {
+    let k = match key_size {
+        1024 => 4,
+        768 => 3,
+        512 => 2,
+        _ => return None,
+    };
+    secret_key.get(384 * k..384 * k + 384 * k + 32)
+}
+
+/// Prepends a fresh X25519 key pair to a Kyber key pair, giving the `x25519 || kyber`
+/// layout of hybrid public and secret keys.
+pub fn hybrid_keypair(kyber_public: Vec<u8>, kyber_secret: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    ([public.as_bytes().as_slice(), &kyber_public].concat(), [secret.to_bytes().as_slice(), &kyber_secret].concat())
+}
+
+/// Hybrid X25519 + Kyber encapsulation. The ciphertext is the ephemeral X25519 public key
+/// followed by the Kyber ciphertext; the shared secret stays safe as long as either primitive holds.
+pub fn hybrid_encapsulate(key_size: usize, public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    let (x25519_public, kyber_public) = split_x25519(public_key)?;
+    let recipient = PublicKey::from(x25519_public);
+
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let x25519_secret = ephemeral.diffie_hellman(&recipient);
+    if !x25519_secret.was_contributory() {
+        return Err(CryptError::new("Invalid X25519 public key"));
+    }
+
+    let (kyber_secret, kyber_cipher) = encapsulate(key_size, kyber_public)?;
+    let shared_secret = combine(&kyber_secret, x25519_secret.as_bytes(), ephemeral_public.as_bytes(), recipient.as_bytes(), &kyber_cipher);
+    Ok((shared_secret, [ephemeral_public.as_bytes().as_slice(), &kyber_cipher].concat()))
+}
+
+/// Recovers the shared secret of `hybrid_encapsulate` with the hybrid secret key.
+pub fn hybrid_decapsulate(key_size: usize, secret_key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptError> {
+    let (x25519_secret, kyber_secret) = split_x25519(secret_key)?;
+    let (ephemeral_public, kyber_cipher) = split_x25519(ciphertext)?;
+
+    let secret = StaticSecret::from(x25519_secret);
+    let x25519_shared = secret.diffie_hellman(&PublicKey::from(ephemeral_public));
+    if !x25519_shared.was_contributory() {
+        return Err(CryptError::new("Invalid X25519 ephemeral key"));
+    }
+
+    let kyber_shared = decapsulate(key_size, kyber_secret, kyber_cipher)?;
+    Ok(combine(&kyber_shared, x25519_shared.as_bytes(), &ephemeral_public, PublicKey::from(&secret).as_bytes(), kyber_cipher))
+}
+
+/// Parses a byte slice into one of the fixed-size fips203 types.
+macro_rules! ml_kem_decode {
+    ($type:ty, $bytes:expr, $what:literal) => {
+        <$type>::try_from_bytes($bytes.try_into().map_err(|_| CryptError::new(concat!("Invalid ML-KEM ", $what, " length")))?)
+            .map_err(CryptError::new)?
+    };
+}
+
+/// Generates an ML-KEM key pair in the FIPS 203 encoding, returning `(public, secret)`.
+pub fn ml_kem_keypair(key_size: usize) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    let (public, secret) = match key_size {
+        1024 => fips203::ml_kem_1024::KG::try_keygen().map(|(ek, dk)| (ek.into_bytes().to_vec(), dk.into_bytes().to_vec())),
+        768 => fips203::ml_kem_768::KG::try_keygen().map(|(ek, dk)| (ek.into_bytes().to_vec(), dk.into_bytes().to_vec())),
+        512 => fips203::ml_kem_512::KG::try_keygen().map(|(ek, dk)| (ek.into_bytes().to_vec(), dk.into_bytes().to_vec())),
+        _ => return Err(CryptError::new("Invalid ML-KEM key size")),
+    }.map_err(CryptError::new)?;
+    Ok((public, secret))
+}
+
+/// ML-KEM encapsulation, returning `(shared_secret, ciphertext)` like `encapsulate`.
+pub fn ml_kem_encapsulate(key_size: usize, public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    let (shared_secret, cipher) = match key_size {
+        1024 => ml_kem_decode!(fips203::ml_kem_1024::EncapsKey, public_key, "public key").try_encaps()
+            .map(|(ssk, ct)| (ssk.into_bytes(), ct.into_bytes().to_vec())),
+        768 => ml_kem_decode!(fips203::ml_kem_768::EncapsKey, public_key, "public key").try_encaps()
+            .map(|(ssk, ct)| (ssk.into_bytes(), ct.into_bytes().to_vec())),
+        512 => ml_kem_decode!(fips203::ml_kem_512::EncapsKey, public_key, "public key").try_encaps()
+            .map(|(ssk, ct)| (ssk.into_bytes(), ct.into_bytes().to_vec())),
+        _ => return Err(CryptError::new("Invalid ML-KEM key size")),
+    }.map_err(CryptError::new)?;
+    Ok((shared_secret.to_vec(), cipher))
+}
+
+/// ML-KEM decapsulation with the FIPS 203 secret key.
+pub fn ml_kem_decapsulate(key_size: usize, secret_key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptError> {
+    let shared_secret = match key_size {
+        1024 => ml_kem_decode!(fips203::ml_kem_1024::DecapsKey, secret_key, "secret key")
+            .try_decaps(&ml_kem_decode!(fips203::ml_kem_1024::CipherText, ciphertext, "ciphertext")),
+        768 => ml_kem_decode!(fips203::ml_kem_768::DecapsKey, secret_key, "secret key")
+            .try_decaps(&ml_kem_decode!(fips203::ml_kem_768::CipherText, ciphertext, "ciphertext")),
+        512 => ml_kem_decode!(fips203::ml_kem_512::DecapsKey, secret_key, "secret key")
+            .try_decaps(&ml_kem_decode!(fips203::ml_kem_512::CipherText, ciphertext, "ciphertext")),
+        _ => return Err(CryptError::new("Invalid ML-KEM key size")),
+    }.map_err(CryptError::new)?;
+    Ok(shared_secret.into_bytes().to_vec())
+}
+
+/// Encapsulates to `public_key` with the given KEM.
+pub fn encapsulate_with(kem: Kem, key_size: usize, public_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    match kem {
+        Kem::Kyber => encapsulate(key_size, public_key),
+        Kem::X25519Kyber => hybrid_encapsulate(key_size, public_key),
+        Kem::MlKem => ml_kem_encapsulate(key_size, public_key),
+    }
+}
+
+/// Decapsulates a ciphertext of the given KEM.
+pub fn decapsulate_with(kem: Kem, key_size: usize, secret_key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptError> {
+    match kem {
+        Kem::Kyber => decapsulate(key_size, secret_key, ciphertext),
+        Kem::X25519Kyber => hybrid_decapsulate(key_size, secret_key, ciphertext),
+        Kem::MlKem => ml_kem_decapsulate(key_size, secret_key, ciphertext),
+    }
+}
+
+fn split_x25519(data: &[u8]) -> Result<([u8; X25519_LEN], &[u8]), CryptError> {
+    match data.split_first_chunk::<X25519_LEN>() {
+        Some((x25519, rest)) => Ok((*x25519, rest)),
+        None => Err(CryptError::new("Hybrid key or ciphertext is too short")),
+    }
+}
+
+/// Binds both shared secrets to the X25519 transcript and the Kyber ciphertext,
+/// in the spirit of the X-Wing combiner.
+fn combine(kyber_secret: &[u8], x25519_secret: &[u8], ephemeral_public: &[u8], recipient_public: &[u8], kyber_cipher: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"crypt_guard x25519-kyber v1");
+    hasher.update(kyber_secret);
+    hasher.update(x25519_secret);
+    hasher.update(ephemeral_public);
+    hasher.update(recipient_public);
+    hasher.update(kyber_cipher);
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kyber_round_trip() {
+        for key_size in [512, 768, 1024] {
+            let (public, secret) = match key_size {
+                1024 => KyberKeypair!(1024),
+                768 => KyberKeypair!(768),
+                _ => KyberKeypair!(512),
+            };
+            let (shared_secret, cipher) = encapsulate(key_size, &public).unwrap();
+            assert_eq!(decapsulate(key_size, &secret, &cipher).unwrap(), shared_secret);
+            assert_eq!(public_from_secret(key_size, &secret), Some(public.as_slice()));
+        }
+        assert!(encapsulate(256, &[0; 32]).is_err());
+    }
+
+    #[test]
+    fn ml_kem_round_trip() {
+        for key_size in [512, 768, 1024] {
+            let (public, secret) = ml_kem_keypair(key_size).unwrap();
+            let (shared_secret, cipher) = encapsulate_with(Kem::MlKem, key_size, &public).unwrap();
+            assert_eq!(decapsulate_with(Kem::MlKem, key_size, &secret, &cipher).unwrap(), shared_secret);
+            assert_eq!(public_from_secret(key_size, &secret), Some(public.as_slice()));
+        }
+        assert!(ml_kem_encapsulate(768, &[0; 10]).is_err());
+        assert!(ml_kem_keypair(256).is_err());
+    }
+
+    #[test]
+    fn kem_ids_round_trip() {
+        for kem in [Kem::Kyber, Kem::X25519Kyber, Kem::MlKem] {
+            assert_eq!(Kem::from_id(kem.id()), Some(kem));
+        }
+        assert_eq!(Kem::from_id(3), None);
+        assert_eq!(Kem::MlKem.name(768), "ml-kem-768");
+    }
+
+    #[test]
+    fn hybrid_round_trip() {
+        let (public, secret) = KyberKeypair!(768);
+        let (hybrid_public, hybrid_secret) = hybrid_keypair(public.clone(), secret.clone());
+        assert_eq!(hybrid_public.len(), X25519_LEN + public.len());
+        assert_eq!(hybrid_secret.len(), X25519_LEN + secret.len());
+
+        let (shared_secret, cipher) = encapsulate_with(Kem::X25519Kyber, 768, &hybrid_public).unwrap();
+        assert_eq!(decapsulate_with(Kem::X25519Kyber, 768, &hybrid_secret, &cipher).unwrap(), shared_secret);
+
+        // The X25519 half is bound in: the Kyber half alone doesn't give the same secret
+        let kyber_only = decapsulate(768, &secret, &cipher[X25519_LEN..]).unwrap();
+        assert_ne!(kyber_only, shared_secret);
+    }
+
+    #[test]
+    fn hybrid_detects_a_tampered_x25519_half() {
+        let (public, secret) = KyberKeypair!(768);
+        let (hybrid_public, hybrid_secret) = hybrid_keypair(public, secret);
+        let (shared_secret, mut cipher) = hybrid_encapsulate(768, &hybrid_public).unwrap();
+        cipher[0] ^= 1;
+        assert_ne!(hybrid_decapsulate(768, &hybrid_secret, &cipher).ok(), Some(shared_secret));
+    }
+
+    #[test]
+    fn hybrid_rejects_short_input() {
+        assert!(hybrid_encapsulate(768, &[0; X25519_LEN - 1]).is_err());
+        assert!(hybrid_decapsulate(768, &[0; X25519_LEN], &[0; 4]).is_err());
+    }
+}
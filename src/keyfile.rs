@@ -0,0 +1,353 @@
+use std::{fmt, io::Write, path::Path};
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305, XNonce};
+use rand::{rngs::OsRng, RngCore};
+use sha3::{Digest, Sha3_256};
+use crate::{armor, container::{read_u32, read_u8, read_vec}, kdf::Kdf, kem, stdio, CryptGuardError, KeyTypes};
+
+/// Magic bytes at the start of every key file written by `keygen`.
+pub const MAGIC: &[u8; 5] = b"CGKEY";
+/// Poly1305 tag appended to sealed keys.
+const TAG_LEN: usize = 16;
+
+/// Current key file format version. Version 1 files carry no protection field,
+/// versions 1 and 2 no embedded public key.
+pub const VERSION: u8 = 3;
+/// Sealing was introduced with version 2; its header layout is what sealed keys are bound to.
+const SEALED_SINCE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyRole {
+    Public,
+    Secret,
+}
+
+impl fmt::Display for KeyRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                KeyRole::Public => "public",
+                KeyRole::Secret => "secret",
+            }
+        )
+    }
+}
+
+impl KeyRole {
+    fn id(&self) -> u8 {
+        match self {
+            KeyRole::Public => 0,
+            KeyRole::Secret => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, CryptGuardError> {
+        match id {
+            0 => Ok(KeyRole::Public),
+            1 => Ok(KeyRole::Secret),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid key role identifier: {}", id))),
+        }
+    }
+}
+
+/// A key together with the algorithm and role it was generated for.
+///
+/// Secret keys can be sealed with a passphrase: `protection` then holds the Argon2id
+/// parameters, `nonce` the XChaCha20Poly1305 nonce, and `key` the encrypted key bytes.
+/// Secret keys written by `keygen` also carry their public key in the clear, because
+/// Falcon and Dilithium can't recompute it from the secret key.
+#[derive(Debug)]
+pub struct KeyFile {
+    pub key_type: KeyTypes,
+    pub role: KeyRole,
+    pub protection: Kdf,
+    pub nonce: Vec<u8>,
+    pub key: Vec<u8>,
+    pub embedded_public: Option<Vec<u8>>,
+}
+
+impl KeyFile {
+    pub fn new(key_type: KeyTypes, role: KeyRole, key: Vec<u8>) -> Self {
+        KeyFile { key_type, role, protection: Kdf::None, nonce: Vec::new(), key, embedded_public: None }
+    }
+
+    /// Embeds the matching public key into a secret key file.
+    pub fn with_public_key(mut self, public_key: Vec<u8>) -> Self {
+        self.embedded_public = Some(public_key);
+        self
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.protection != Kdf::None
+    }
+
+    /// Length of the key material itself, without the tag of a sealed key.
+    pub fn key_len(&self) -> usize {
+        match self.is_sealed() {
+            true => self.key.len().saturating_sub(TAG_LEN),
+            false => self.key.len(),
+        }
+    }
+
+    /// The public key: the key itself, the one embedded in a secret key file, or for
+    /// unsealed Kyber secret keys the copy that is part of the key material.
+    pub fn public_key(&self) -> Option<&[u8]> {
+        match (self.role, &self.embedded_public) {
+            (KeyRole::Public, _) => Some(&self.key),
+            (KeyRole::Secret, Some(public_key)) => Some(public_key),
+            (KeyRole::Secret, None) if self.key_type.is_kyber() && !self.is_sealed() => kem::public_from_secret(self.key_type.key_size(), &self.key),
+            (KeyRole::Secret, None) => None,
+        }
+    }
+
+    /// SHA3-256 of the public key, hex encoded. Public and secret key of a pair share it.
+    pub fn fingerprint(&self) -> Option<String> {
+        self.public_key().map(|public_key| hex::encode(Sha3_256::digest(public_key)))
+    }
+
+    /// `path (algorithm, fingerprint)` for progress messages.
+    pub fn describe(&self, path: &Path) -> String {
+        match self.fingerprint() {
+            Some(fingerprint) => format!("{} ({}, fingerprint {})", path.display(), self.key_type, fingerprint),
+            None => format!("{} ({}, fingerprint unknown)", path.display(), self.key_type),
+        }
+    }
+
+    /// Encrypts the key bytes with a key derived from `passphrase`.
+    pub fn seal(&mut self, passphrase: &str) -> Result<(), CryptGuardError> {
+        if self.is_sealed() {
+            return Err(CryptGuardError::ParseError("Key is already protected by a passphrase".to_string()));
+        }
+
+        let protection = Kdf::argon2id();
+        let wrapping_key = protection.derive_key(passphrase)?;
+        let mut nonce = vec![0u8; 24];
+        OsRng.fill_bytes(&mut nonce);
+
+        let sealed = XChaCha20Poly1305::new(&wrapping_key.into())
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &self.key, aad: &self.seal_aad() })
+            .map_err(|_| CryptGuardError::CryptError(::crypt_guard::error::CryptError::new("Sealing the secret key failed")))?;
+
+        self.protection = protection;
+        self.nonce = nonce;
+        self.key = sealed;
+        Ok(())
+    }
+
+    /// Decrypts a sealed key in place; a wrong passphrase is reported as an authentication failure.
+    pub fn unseal(&mut self, passphrase: &str) -> Result<(), CryptGuardError> {
+        if !self.is_sealed() {
+            return Ok(());
+        }
+
+        let wrapping_key = self.protection.derive_key(passphrase)?;
+        let key = XChaCha20Poly1305::new(&wrapping_key.into())
+            .decrypt(XNonce::from_slice(&self.nonce), Payload { msg: &self.key, aad: &self.seal_aad() })
+            .map_err(|_| CryptGuardError::AuthError("Wrong passphrase for the secret key".to_string()))?;
+
+        self.protection = Kdf::None;
+        self.nonce = Vec::new();
+        self.key = key;
+        Ok(())
+    }
+
+    /// Associated data of a sealed key: the version 2 header and the embedded public key,
+    /// so neither the key type nor the public key can be swapped without breaking the seal.
+    fn seal_aad(&self) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(MAGIC.len() + 3);
+        aad.extend_from_slice(MAGIC);
+        aad.push(SEALED_SINCE);
+        aad.push(self.key_type.id());
+        aad.push(self.role.id());
+        if let Some(public_key) = &self.embedded_public {
+            aad.extend_from_slice(public_key);
+        }
+        aad
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(self.key_type.id());
+        buf.push(self.role.id());
+        self.protection.encode(&mut buf);
+        if self.is_sealed() {
+            buf.extend_from_slice(&self.nonce);
+        }
+        buf.extend_from_slice(&(self.key.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.key);
+
+        let public_key = self.embedded_public.as_deref().unwrap_or_default();
+        buf.extend_from_slice(&(public_key.len() as u32).to_be_bytes());
+        buf.extend_from_slice(public_key);
+        buf
+    }
+
+    /// Parses a key file, armored or binary.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CryptGuardError> {
+        let data = armor::dearmor(data)?;
+        if !data.starts_with(MAGIC) {
+            return legacy(&data);
+        }
+
+        let mut reader = &data[MAGIC.len()..];
+        let version = read_u8(&mut reader)?;
+        if version == 0 || version > VERSION {
+            return Err(CryptGuardError::ParseError(format!("Unsupported key file version: {}", version)));
+        }
+
+        let key_type = KeyTypes::from_id(read_u8(&mut reader)?)?;
+        let role = KeyRole::from_id(read_u8(&mut reader)?)?;
+        let protection = if version == 1 { Kdf::None } else { Kdf::decode(&mut reader)? };
+        let nonce = match protection {
+            Kdf::None => Vec::new(),
+            _ => read_vec(&mut reader, 24)?,
+        };
+
+        let len = read_u32(&mut reader)? as usize;
+        if reader.len() < len {
+            return Err(CryptGuardError::ParseError(format!("Key file is corrupted: expected {} key bytes, found {}", len, reader.len())));
+        }
+        let key = read_vec(&mut reader, len)?;
+
+        let embedded_public = match version < 3 {
+            true => None,
+            false => match read_u32(&mut reader)? as usize {
+                0 => None,
+                len if reader.len() < len => {
+                    return Err(CryptGuardError::ParseError(format!("Key file is corrupted: expected {} public key bytes, found {}", len, reader.len())));
+                },
+                len => Some(read_vec(&mut reader, len)?),
+            },
+        };
+        if !reader.is_empty() {
+            return Err(CryptGuardError::ParseError(format!("Key file is corrupted: {} trailing bytes", reader.len())));
+        }
+
+        Ok(KeyFile { key_type, role, protection, nonce, key, embedded_public })
+    }
+
+    pub fn read(path: &Path) -> Result<Self, CryptGuardError> {
+        let data = crate::read_file(path)?;
+        Self::from_bytes(&data)
+            .map_err(|e| CryptGuardError::ParseError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Writes the key file, optionally armored, readable only by the owner if it holds a secret key.
+    pub fn write(&self, path: &Path, armored: bool) -> Result<(), CryptGuardError> {
+        let data = match (armored, self.role) {
+            (true, KeyRole::Public) => armor::encode(armor::PUBLIC_KEY, &self.to_bytes()),
+            (true, KeyRole::Secret) => armor::encode(armor::SECRET_KEY, &self.to_bytes()),
+            (false, _) => self.to_bytes(),
+        };
+
+        if stdio::is_stdio(path) {
+            stdio::write_output(path, &data)?;
+            return Ok(());
+        }
+        // Written to a temporary file and renamed, so the mode applies even when replacing a key
+        // and an interrupted `change-passphrase` can't leave a truncated secret key behind.
+        let mut file = stdio::AtomicFile::create(path, self.role == KeyRole::Secret)?;
+        file.write_all(&data)?;
+        file.persist()?;
+        Ok(())
+    }
+
+    /// Fails with a descriptive error unless this is a `role` key accepted by `is_allowed`.
+    pub fn require(&self, role: KeyRole, purpose: &str, is_allowed: impl Fn(&KeyTypes) -> bool) -> Result<(), CryptGuardError> {
+        if self.role != role || !is_allowed(&self.key_type) {
+            return Err(CryptGuardError::ParseError(format!(
+                "Got a {} {} key, but {} needs a {} key",
+                self.key_type, self.role, purpose, role
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Recognises raw keys written before key files carried a header by their length.
+fn legacy(data: &[u8]) -> Result<KeyFile, CryptGuardError> {
+    let (key_type, role) = match data.len() {
+        1568 => (KeyTypes::Kyber1024, KeyRole::Public),
+        3168 => (KeyTypes::Kyber1024, KeyRole::Secret),
+        1184 => (KeyTypes::Kyber768, KeyRole::Public),
+        2400 => (KeyTypes::Kyber768, KeyRole::Secret),
+        800 => (KeyTypes::Kyber512, KeyRole::Public),
+        1632 => (KeyTypes::Kyber512, KeyRole::Secret),
+        1793 => (KeyTypes::Falcon1024, KeyRole::Public),
+        2305 => (KeyTypes::Falcon1024, KeyRole::Secret),
+        897 => (KeyTypes::Falcon512, KeyRole::Public),
+        1281 => (KeyTypes::Falcon512, KeyRole::Secret),
+        2592 => (KeyTypes::Dilithium5, KeyRole::Public),
+        4864 => (KeyTypes::Dilithium5, KeyRole::Secret),
+        1952 => (KeyTypes::Dilithium3, KeyRole::Public),
+        4000 => (KeyTypes::Dilithium3, KeyRole::Secret),
+        1312 => (KeyTypes::Dilithium2, KeyRole::Public),
+        2528 => (KeyTypes::Dilithium2, KeyRole::Secret),
+        len => return Err(CryptGuardError::ParseError(format!("Not a crypt_guard key file ({} bytes, no header)", len))),
+    };
+    Ok(KeyFile::new(key_type, role, data.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded() -> Vec<u8> {
+        KeyFile::new(KeyTypes::Kyber768, KeyRole::Secret, vec![7; 64]).to_bytes()
+    }
+
+    fn encoded_with_public() -> Vec<u8> {
+        let mut key = KeyFile::new(KeyTypes::Kyber768, KeyRole::Secret, vec![7; 64]);
+        key.embedded_public = Some(vec![5; 32]);
+        key.to_bytes()
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = KeyFile::from_bytes(&encoded()).unwrap();
+        assert_eq!(key.key_type, KeyTypes::Kyber768);
+        assert_eq!(key.role, KeyRole::Secret);
+        assert_eq!(key.key, vec![7; 64]);
+        assert_eq!(key.embedded_public, None);
+
+        let key = KeyFile::from_bytes(&encoded_with_public()).unwrap();
+        assert_eq!(key.embedded_public, Some(vec![5; 32]));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = encoded();
+        for len in MAGIC.len()..MAGIC.len() + 7 {
+            assert!(KeyFile::from_bytes(&data[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_key() {
+        // Cut into the key itself and drop the (empty) public key field behind it
+        let data = encoded();
+        assert!(matches!(KeyFile::from_bytes(&data[..data.len() - 5]), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_truncated_embedded_public_key() {
+        let data = encoded_with_public();
+        for cut in [1, 31] {
+            assert!(matches!(KeyFile::from_bytes(&data[..data.len() - cut]), Err(CryptGuardError::ParseError(_))), "{} bytes cut", cut);
+        }
+        let mut data = encoded();
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(KeyFile::from_bytes(&data), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut data = encoded();
+        data.push(0);
+        assert!(matches!(KeyFile::from_bytes(&data), Err(CryptGuardError::ParseError(_))));
+    }
+}
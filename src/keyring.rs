@@ -0,0 +1,235 @@
+use std::{env, fs, path::{Path, PathBuf}};
+use crate::{keyfile::{KeyFile, KeyRole}, stdio, CryptGuardError};
+
+/// Overrides the keyring location, mainly for CI and tests.
+pub const KEYRING_ENV: &str = "CRYPT_GUARD_KEYRING";
+
+const LABEL_FILE: &str = "label";
+
+/// Shortest fingerprint prefix accepted as a key reference.
+const MIN_PREFIX_LEN: usize = 8;
+
+/// A named key pair in the keyring, stored as `<name>/<name>.pub` and `<name>/<name>.sec`
+/// (the same layout `keygen -d` produces) plus an optional `label` file.
+#[derive(Debug)]
+pub struct Identity {
+    pub name: String,
+    pub label: Option<String>,
+    dir: PathBuf,
+}
+
+impl Identity {
+    pub fn key_path(&self, role: KeyRole) -> PathBuf {
+        let extension = match role {
+            KeyRole::Public => "pub",
+            KeyRole::Secret => "sec",
+        };
+        self.dir.join(format!("{}.{}", self.name, extension))
+    }
+
+    pub fn has(&self, role: KeyRole) -> bool {
+        self.key_path(role).is_file()
+    }
+
+    pub fn read(&self, role: KeyRole) -> Result<KeyFile, CryptGuardError> {
+        KeyFile::read(&self.key_path(role))
+    }
+
+    /// The key file describing the identity: the public key if present, else the secret key.
+    pub fn primary_key(&self) -> Option<KeyFile> {
+        [KeyRole::Public, KeyRole::Secret].into_iter()
+            .filter(|role| self.has(*role))
+            .find_map(|role| self.read(role).ok())
+    }
+
+    pub fn fingerprint(&self) -> Option<String> {
+        [KeyRole::Public, KeyRole::Secret].into_iter()
+            .filter(|role| self.has(*role))
+            .find_map(|role| self.read(role).ok()?.fingerprint())
+    }
+
+    pub fn set_label(&mut self, label: &str) -> Result<(), CryptGuardError> {
+        fs::write(self.dir.join(LABEL_FILE), format!("{}\n", label))?;
+        self.label = Some(label.to_string());
+        Ok(())
+    }
+}
+
+pub struct Keyring {
+    root: PathBuf,
+}
+
+impl Keyring {
+    /// Opens the keyring at `$CRYPT_GUARD_KEYRING`, `$XDG_DATA_HOME/crypt_guard` or `~/.local/share/crypt_guard`.
+    pub fn open() -> Result<Self, CryptGuardError> {
+        let root = match (env::var_os(KEYRING_ENV), env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+            (Some(dir), _, _) => PathBuf::from(dir),
+            (None, Some(data_home), _) if !data_home.is_empty() => PathBuf::from(data_home).join("crypt_guard"),
+            (None, _, Some(home)) => PathBuf::from(home).join(".local").join("share").join("crypt_guard"),
+            _ => return Err(CryptGuardError::ParseError(format!("Can't locate the keyring; set {} or XDG_DATA_HOME", KEYRING_ENV))),
+        };
+        Ok(Keyring { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// All identities, sorted by name. A keyring that doesn't exist yet is empty.
+    pub fn identities(&self) -> Result<Vec<Identity>, CryptGuardError> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut identities = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if let (true, Some(name)) = (entry.file_type()?.is_dir(), entry.file_name().to_str()) {
+                identities.push(self.identity(name));
+            }
+        }
+        identities.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(identities)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Identity> {
+        match valid_name(name) && self.root.join(name).is_dir() {
+            true => Some(self.identity(name)),
+            false => None,
+        }
+    }
+
+    pub fn create(&self, name: &str, label: Option<&String>) -> Result<Identity, CryptGuardError> {
+        if !valid_name(name) {
+            return Err(CryptGuardError::ParseError(format!("Invalid key name {:?}; use letters, digits, '.', '_', '-' and '@'", name)));
+        }
+        if self.get(name).is_some() {
+            return Err(CryptGuardError::ParseError(format!("The keyring already holds a key named {}", name)));
+        }
+
+        fs::create_dir_all(self.root.join(name))?;
+        let mut identity = self.identity(name);
+        if let Some(label) = label {
+            identity.set_label(label)?;
+        }
+        Ok(identity)
+    }
+
+    pub fn delete(&self, identity: Identity) -> Result<(), CryptGuardError> {
+        fs::remove_dir_all(&identity.dir)?;
+        Ok(())
+    }
+
+    /// Finds the identity holding a `role` key by exact name, then by label, then by fingerprint prefix.
+    pub fn find(&self, query: &str, role: KeyRole) -> Result<Identity, CryptGuardError> {
+        let candidates: Vec<Identity> = self.identities()?.into_iter().filter(|identity| identity.has(role)).collect();
+
+        let by_name = |identity: &Identity| identity.name == query;
+        let by_label = |identity: &Identity| identity.label.as_deref().is_some_and(|label| label.eq_ignore_ascii_case(query));
+        let by_fingerprint = |identity: &Identity| {
+            query.len() >= MIN_PREFIX_LEN
+                && query.chars().all(|c| c.is_ascii_hexdigit())
+                && identity.fingerprint().is_some_and(|fingerprint| fingerprint.starts_with(&query.to_lowercase()))
+        };
+
+        for matches in [&by_name as &dyn Fn(&Identity) -> bool, &by_label, &by_fingerprint] {
+            let mut found: Vec<&Identity> = candidates.iter().filter(|identity| matches(identity)).collect();
+            match found.len() {
+                0 => continue,
+                1 => return Ok(self.identity(&found.remove(0).name)),
+                _ => {
+                    let names: Vec<&str> = found.iter().map(|identity| identity.name.as_str()).collect();
+                    return Err(CryptGuardError::ParseError(format!("{} is ambiguous, it matches {}", query, names.join(", "))));
+                },
+            }
+        }
+
+        Err(CryptGuardError::ParseError(format!(
+            "{} is neither a key file nor the name, label or fingerprint of a {} key in the keyring at {}",
+            query, role, self.root.display()
+        )))
+    }
+
+    fn identity(&self, name: &str) -> Identity {
+        let dir = self.root.join(name);
+        let label = fs::read_to_string(dir.join(LABEL_FILE)).ok()
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty());
+        Identity { name: name.to_string(), label, dir }
+    }
+}
+
+/// Returns `key` if it names an existing file, and otherwise looks it up in the keyring.
+pub fn resolve(key: &Path, role: KeyRole) -> Result<PathBuf, CryptGuardError> {
+    if key.exists() || stdio::is_stdio(key) {
+        return Ok(key.to_path_buf());
+    }
+
+    let query = key.to_str()
+        .ok_or_else(|| CryptGuardError::ParseError(format!("Key reference is not valid UTF-8: {}", key.display())))?;
+    let identity = Keyring::open()?.find(query, role)?;
+    Ok(identity.key_path(role))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyTypes;
+
+    fn scratch(name: &str) -> Keyring {
+        let root = env::temp_dir().join(format!("crypt_guard-keyring-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        Keyring { root }
+    }
+
+    fn add(keyring: &Keyring, name: &str, label: Option<&str>, public_key: Vec<u8>) -> String {
+        let identity = keyring.create(name, label.map(str::to_string).as_ref()).unwrap();
+        let key = KeyFile::new(KeyTypes::Kyber768, KeyRole::Public, public_key);
+        key.write(&identity.key_path(KeyRole::Public), false).unwrap();
+        key.fingerprint().unwrap()
+    }
+
+    #[test]
+    fn add_and_list() {
+        let keyring = scratch("add");
+        assert!(keyring.identities().unwrap().is_empty());
+
+        add(&keyring, "bob", None, vec![2; 32]);
+        add(&keyring, "alice", Some("Alice Example"), vec![1; 32]);
+
+        let identities = keyring.identities().unwrap();
+        let names: Vec<&str> = identities.iter().map(|identity| identity.name.as_str()).collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert_eq!(identities[0].label.as_deref(), Some("Alice Example"));
+        assert!(identities[0].has(KeyRole::Public) && !identities[0].has(KeyRole::Secret));
+
+        assert!(keyring.create("alice", None).is_err());
+        for name in ["", ".hidden", "../escape", "a/b"] {
+            assert!(keyring.create(name, None).is_err(), "{:?}", name);
+        }
+        fs::remove_dir_all(keyring.root()).unwrap();
+    }
+
+    #[test]
+    fn finds_by_name_label_and_fingerprint_prefix() {
+        let keyring = scratch("find");
+        let fingerprint = add(&keyring, "alice", Some("Alice Example"), vec![1; 32]);
+        add(&keyring, "bob", None, vec![2; 32]);
+
+        assert_eq!(keyring.find("alice", KeyRole::Public).unwrap().name, "alice");
+        assert_eq!(keyring.find("alice example", KeyRole::Public).unwrap().name, "alice");
+        assert_eq!(keyring.find(&fingerprint[..MIN_PREFIX_LEN], KeyRole::Public).unwrap().name, "alice");
+        assert_eq!(keyring.find(&fingerprint.to_uppercase(), KeyRole::Public).unwrap().name, "alice");
+
+        assert!(keyring.find(&fingerprint[..MIN_PREFIX_LEN - 1], KeyRole::Public).is_err());
+        assert!(keyring.find("alice", KeyRole::Secret).is_err());
+        assert!(keyring.find("carol", KeyRole::Public).is_err());
+        fs::remove_dir_all(keyring.root()).unwrap();
+    }
+}
@@ -1,14 +1,69 @@
 use std::path::Path;
-use clap::{arg, Arg, ArgAction, Command};
-use std::{path::PathBuf, fmt, fs::{File, self}, io::Write};
+use clap::{arg, Arg, ArgAction, ArgMatches, Command};
+use std::{collections::{HashMap, HashSet}, path::PathBuf, fmt, fs, io::{Read, Write}};
+use rayon::prelude::*;
 use ::crypt_guard::{*, error::*};
-use hex;
 
+mod archive;
+mod armor;
+mod cleartext;
+mod composite;
+mod container;
+mod digest;
+mod kdf;
+mod kem;
+mod keyfile;
+mod keyring;
+mod manifest;
+mod ml_dsa;
+mod packet;
+mod slh_dsa;
+mod passphrase;
+mod recipient;
+mod payload;
+mod stdio;
+mod stream;
+
+use armor::{ArmorWriter, Encoding};
+use container::{EmbeddedSignature, Header};
+use kdf::Kdf;
+use kem::Kem;
+use keyfile::{KeyFile, KeyRole};
+use keyring::Keyring;
+use packet::Packet;
+use passphrase::Source;
+use recipient::Stanza;
+
+/// Errors surfaced by the CLI. Each variant maps to its own process exit code:
+///
+/// | code | meaning                                                  |
+/// |------|----------------------------------------------------------|
+/// | 0    | success                                                  |
+/// | 1    | other cryptographic failure                              |
+/// | 2    | bad arguments (invalid algorithm, key size, input format) |
+/// | 3    | I/O error (missing file, permission denied, broken pipe)  |
+/// | 4    | authentication failure (wrong key/passphrase, tampering) |
+/// | 5    | invalid signature                                        |
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum CryptGuardError {
     IoError(std::io::Error),
     ParseError(String),
     CryptError(CryptError),
+    AuthError(String),
+    SignatureError(String),
+}
+
+impl CryptGuardError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CryptGuardError::CryptError(_) => 1,
+            CryptGuardError::ParseError(_) => 2,
+            CryptGuardError::IoError(_) => 3,
+            CryptGuardError::AuthError(_) => 4,
+            CryptGuardError::SignatureError(_) => 5,
+        }
+    }
 }
 
 impl From<std::io::Error> for CryptGuardError {
@@ -29,12 +84,13 @@ impl fmt::Display for CryptGuardError {
             CryptGuardError::IoError(err) => write!(f, "I/O Error: {}", err),
             CryptGuardError::ParseError(err) => write!(f, "Parse Error: {}", err),
             CryptGuardError::CryptError(err) => write!(f, "Cryptographic Error: {}", err),
+            CryptGuardError::AuthError(err) => write!(f, "Authentication Error: {}", err),
+            CryptGuardError::SignatureError(err) => write!(f, "Signature Error: {}", err),
         }
     }
 }
 
-
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum KeyTypes {
     Falcon1024,
     Falcon512,
@@ -44,6 +100,26 @@ enum KeyTypes {
     Dilithium5,
     Dilithium3,
     Dilithium2,
+    X25519Kyber1024,
+    X25519Kyber768,
+    X25519Kyber512,
+    Ed25519Falcon1024,
+    Ed25519Falcon512,
+    Ed25519Dilithium5,
+    Ed25519Dilithium3,
+    Ed25519Dilithium2,
+    MlKem1024,
+    MlKem768,
+    MlKem512,
+    MlDsa87,
+    MlDsa65,
+    MlDsa44,
+    SlhDsaSha2_128s,
+    SlhDsaSha2_128f,
+    SlhDsaSha2_192s,
+    SlhDsaSha2_192f,
+    SlhDsaSha2_256s,
+    SlhDsaSha2_256f,
 }
 
 impl fmt::Display for KeyTypes {
@@ -60,14 +136,36 @@ impl fmt::Display for KeyTypes {
                 KeyTypes::Dilithium5 => "dilithium5",
                 KeyTypes::Dilithium3 => "dilithium3",
                 KeyTypes::Dilithium2 => "dilithium2",
+                KeyTypes::X25519Kyber1024 => "x25519kyber1024",
+                KeyTypes::X25519Kyber768 => "x25519kyber768",
+                KeyTypes::X25519Kyber512 => "x25519kyber512",
+                KeyTypes::Ed25519Falcon1024 => "ed25519falcon1024",
+                KeyTypes::Ed25519Falcon512 => "ed25519falcon512",
+                KeyTypes::Ed25519Dilithium5 => "ed25519dilithium5",
+                KeyTypes::Ed25519Dilithium3 => "ed25519dilithium3",
+                KeyTypes::Ed25519Dilithium2 => "ed25519dilithium2",
+                KeyTypes::MlKem1024 => "ml-kem-1024",
+                KeyTypes::MlKem768 => "ml-kem-768",
+                KeyTypes::MlKem512 => "ml-kem-512",
+                KeyTypes::MlDsa87 => "ml-dsa-87",
+                KeyTypes::MlDsa65 => "ml-dsa-65",
+                KeyTypes::MlDsa44 => "ml-dsa-44",
+                KeyTypes::SlhDsaSha2_128s => "slh-dsa-sha2-128s",
+                KeyTypes::SlhDsaSha2_128f => "slh-dsa-sha2-128f",
+                KeyTypes::SlhDsaSha2_192s => "slh-dsa-sha2-192s",
+                KeyTypes::SlhDsaSha2_192f => "slh-dsa-sha2-192f",
+                KeyTypes::SlhDsaSha2_256s => "slh-dsa-sha2-256s",
+                KeyTypes::SlhDsaSha2_256f => "slh-dsa-sha2-256f",
             }
         )
     }
 }
 
 impl KeyTypes {
-    fn from_str(input: &str) -> Result<Self, CryptError> {
-        match input.to_lowercase().as_str() {
+    fn from_str(input: &str) -> Result<Self, CryptGuardError> {
+        // ML-KEM-768, ml_kem_768 and mlkem768 all name the same parameter set
+        let normalized: String = input.to_lowercase().chars().filter(|c| !matches!(c, '-' | '_')).collect();
+        match normalized.as_str() {
             "falcon1024" => Ok(KeyTypes::Falcon1024),
             "falcon512" => Ok(KeyTypes::Falcon512),
             "kyber1024" => Ok(KeyTypes::Kyber1024),
@@ -76,7 +174,218 @@ impl KeyTypes {
             "dilithium5" => Ok(KeyTypes::Dilithium5),
             "dilithium3" => Ok(KeyTypes::Dilithium3),
             "dilithium2" => Ok(KeyTypes::Dilithium2),
-            _ => Err(CryptError::new(format!("Invalid algorithm: {}", input).as_str())),
+            "x25519kyber1024" => Ok(KeyTypes::X25519Kyber1024),
+            "x25519kyber768" => Ok(KeyTypes::X25519Kyber768),
+            "x25519kyber512" => Ok(KeyTypes::X25519Kyber512),
+            "ed25519falcon1024" => Ok(KeyTypes::Ed25519Falcon1024),
+            "ed25519falcon512" => Ok(KeyTypes::Ed25519Falcon512),
+            "ed25519dilithium5" => Ok(KeyTypes::Ed25519Dilithium5),
+            "ed25519dilithium3" => Ok(KeyTypes::Ed25519Dilithium3),
+            "ed25519dilithium2" => Ok(KeyTypes::Ed25519Dilithium2),
+            "mlkem1024" | "fips203mlkem1024" => Ok(KeyTypes::MlKem1024),
+            "mlkem768" | "fips203mlkem768" => Ok(KeyTypes::MlKem768),
+            "mlkem512" | "fips203mlkem512" => Ok(KeyTypes::MlKem512),
+            "mldsa87" | "fips204mldsa87" => Ok(KeyTypes::MlDsa87),
+            "mldsa65" | "fips204mldsa65" => Ok(KeyTypes::MlDsa65),
+            "mldsa44" | "fips204mldsa44" => Ok(KeyTypes::MlDsa44),
+            "slhdsasha2128s" | "sphincssha2128s" | "sphincs+sha2128s" => Ok(KeyTypes::SlhDsaSha2_128s),
+            "slhdsasha2128f" | "sphincssha2128f" | "sphincs+sha2128f" => Ok(KeyTypes::SlhDsaSha2_128f),
+            "slhdsasha2192s" | "sphincssha2192s" | "sphincs+sha2192s" => Ok(KeyTypes::SlhDsaSha2_192s),
+            "slhdsasha2192f" | "sphincssha2192f" | "sphincs+sha2192f" => Ok(KeyTypes::SlhDsaSha2_192f),
+            "slhdsasha2256s" | "sphincssha2256s" | "sphincs+sha2256s" => Ok(KeyTypes::SlhDsaSha2_256s),
+            "slhdsasha2256f" | "sphincssha2256f" | "sphincs+sha2256f" => Ok(KeyTypes::SlhDsaSha2_256f),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid algorithm: {}", input))),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            KeyTypes::Falcon1024 => 1,
+            KeyTypes::Falcon512 => 2,
+            KeyTypes::Kyber1024 => 3,
+            KeyTypes::Kyber768 => 4,
+            KeyTypes::Kyber512 => 5,
+            KeyTypes::Dilithium5 => 6,
+            KeyTypes::Dilithium3 => 7,
+            KeyTypes::Dilithium2 => 8,
+            KeyTypes::X25519Kyber1024 => 9,
+            KeyTypes::X25519Kyber768 => 10,
+            KeyTypes::X25519Kyber512 => 11,
+            KeyTypes::Ed25519Falcon1024 => 12,
+            KeyTypes::Ed25519Falcon512 => 13,
+            KeyTypes::Ed25519Dilithium5 => 14,
+            KeyTypes::Ed25519Dilithium3 => 15,
+            KeyTypes::Ed25519Dilithium2 => 16,
+            KeyTypes::MlKem1024 => 17,
+            KeyTypes::MlKem768 => 18,
+            KeyTypes::MlKem512 => 19,
+            KeyTypes::MlDsa87 => 20,
+            KeyTypes::MlDsa65 => 21,
+            KeyTypes::MlDsa44 => 22,
+            KeyTypes::SlhDsaSha2_128s => 23,
+            KeyTypes::SlhDsaSha2_128f => 24,
+            KeyTypes::SlhDsaSha2_192s => 25,
+            KeyTypes::SlhDsaSha2_192f => 26,
+            KeyTypes::SlhDsaSha2_256s => 27,
+            KeyTypes::SlhDsaSha2_256f => 28,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, CryptGuardError> {
+        match id {
+            1 => Ok(KeyTypes::Falcon1024),
+            2 => Ok(KeyTypes::Falcon512),
+            3 => Ok(KeyTypes::Kyber1024),
+            4 => Ok(KeyTypes::Kyber768),
+            5 => Ok(KeyTypes::Kyber512),
+            6 => Ok(KeyTypes::Dilithium5),
+            7 => Ok(KeyTypes::Dilithium3),
+            8 => Ok(KeyTypes::Dilithium2),
+            9 => Ok(KeyTypes::X25519Kyber1024),
+            10 => Ok(KeyTypes::X25519Kyber768),
+            11 => Ok(KeyTypes::X25519Kyber512),
+            12 => Ok(KeyTypes::Ed25519Falcon1024),
+            13 => Ok(KeyTypes::Ed25519Falcon512),
+            14 => Ok(KeyTypes::Ed25519Dilithium5),
+            15 => Ok(KeyTypes::Ed25519Dilithium3),
+            16 => Ok(KeyTypes::Ed25519Dilithium2),
+            17 => Ok(KeyTypes::MlKem1024),
+            18 => Ok(KeyTypes::MlKem768),
+            19 => Ok(KeyTypes::MlKem512),
+            20 => Ok(KeyTypes::MlDsa87),
+            21 => Ok(KeyTypes::MlDsa65),
+            22 => Ok(KeyTypes::MlDsa44),
+            23 => Ok(KeyTypes::SlhDsaSha2_128s),
+            24 => Ok(KeyTypes::SlhDsaSha2_128f),
+            25 => Ok(KeyTypes::SlhDsaSha2_192s),
+            26 => Ok(KeyTypes::SlhDsaSha2_192f),
+            27 => Ok(KeyTypes::SlhDsaSha2_256s),
+            28 => Ok(KeyTypes::SlhDsaSha2_256f),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid key type identifier: {}", id))),
+        }
+    }
+
+    /// Every key type, in identifier order.
+    fn all() -> impl Iterator<Item = KeyTypes> {
+        (1..=u8::MAX).map_while(|id| KeyTypes::from_id(id).ok())
+    }
+
+    /// The size argument the crypt_guard macros expect for this key type;
+    /// the parameter set number for ML-KEM and ML-DSA and the security strength in bits for SLH-DSA.
+    fn key_size(&self) -> usize {
+        match self {
+            KeyTypes::Falcon1024 | KeyTypes::Kyber1024 | KeyTypes::X25519Kyber1024 | KeyTypes::Ed25519Falcon1024 | KeyTypes::MlKem1024 => 1024,
+            KeyTypes::Kyber768 | KeyTypes::X25519Kyber768 | KeyTypes::MlKem768 => 768,
+            KeyTypes::Falcon512 | KeyTypes::Kyber512 | KeyTypes::X25519Kyber512 | KeyTypes::Ed25519Falcon512 | KeyTypes::MlKem512 => 512,
+            KeyTypes::MlDsa87 => 87,
+            KeyTypes::MlDsa65 => 65,
+            KeyTypes::MlDsa44 => 44,
+            KeyTypes::SlhDsaSha2_128s | KeyTypes::SlhDsaSha2_128f => 128,
+            KeyTypes::SlhDsaSha2_192s | KeyTypes::SlhDsaSha2_192f => 192,
+            KeyTypes::SlhDsaSha2_256s | KeyTypes::SlhDsaSha2_256f => 256,
+            KeyTypes::Dilithium5 | KeyTypes::Ed25519Dilithium5 => 5,
+            KeyTypes::Dilithium3 | KeyTypes::Ed25519Dilithium3 => 3,
+            KeyTypes::Dilithium2 | KeyTypes::Ed25519Dilithium2 => 2,
+        }
+    }
+
+    /// NIST post-quantum security category.
+    fn security_level(&self) -> u8 {
+        match self {
+            KeyTypes::Kyber512 | KeyTypes::Falcon512 | KeyTypes::X25519Kyber512 | KeyTypes::Ed25519Falcon512 | KeyTypes::MlKem512 => 1,
+            KeyTypes::Dilithium2 | KeyTypes::Ed25519Dilithium2 | KeyTypes::MlDsa44 => 2,
+            KeyTypes::Kyber768 | KeyTypes::Dilithium3 | KeyTypes::X25519Kyber768 | KeyTypes::Ed25519Dilithium3
+                | KeyTypes::MlKem768 | KeyTypes::MlDsa65 => 3,
+            KeyTypes::Kyber1024 | KeyTypes::Falcon1024 | KeyTypes::Dilithium5 | KeyTypes::X25519Kyber1024
+                | KeyTypes::Ed25519Falcon1024 | KeyTypes::Ed25519Dilithium5 | KeyTypes::MlKem1024 | KeyTypes::MlDsa87 => 5,
+            KeyTypes::SlhDsaSha2_128s | KeyTypes::SlhDsaSha2_128f => 1,
+            KeyTypes::SlhDsaSha2_192s | KeyTypes::SlhDsaSha2_192f => 3,
+            KeyTypes::SlhDsaSha2_256s | KeyTypes::SlhDsaSha2_256f => 5,
+        }
+    }
+
+    fn is_kyber(&self) -> bool {
+        matches!(self, KeyTypes::Kyber1024 | KeyTypes::Kyber768 | KeyTypes::Kyber512)
+    }
+
+    /// The KEM of key types `encrypt` and `decrypt` accept.
+    fn kem(&self) -> Option<Kem> {
+        match self {
+            KeyTypes::Kyber1024 | KeyTypes::Kyber768 | KeyTypes::Kyber512 => Some(Kem::Kyber),
+            KeyTypes::X25519Kyber1024 | KeyTypes::X25519Kyber768 | KeyTypes::X25519Kyber512 => Some(Kem::X25519Kyber),
+            KeyTypes::MlKem1024 | KeyTypes::MlKem768 | KeyTypes::MlKem512 => Some(Kem::MlKem),
+            _ => None,
+        }
+    }
+
+    /// Ed25519 combined with Falcon or Dilithium; both signatures must verify.
+    fn is_composite(&self) -> bool {
+        matches!(
+            self,
+            KeyTypes::Ed25519Falcon1024 | KeyTypes::Ed25519Falcon512
+                | KeyTypes::Ed25519Dilithium5 | KeyTypes::Ed25519Dilithium3 | KeyTypes::Ed25519Dilithium2
+        )
+    }
+
+    /// The post-quantum signature algorithm, which for composite keys is their second half.
+    fn signature_algorithm(&self) -> Option<SignatureAlgorithm> {
+        match self {
+            KeyTypes::Falcon1024 | KeyTypes::Falcon512
+                | KeyTypes::Ed25519Falcon1024 | KeyTypes::Ed25519Falcon512 => Some(SignatureAlgorithm::Falcon),
+            KeyTypes::Dilithium5 | KeyTypes::Dilithium3 | KeyTypes::Dilithium2
+                | KeyTypes::Ed25519Dilithium5 | KeyTypes::Ed25519Dilithium3 | KeyTypes::Ed25519Dilithium2 => Some(SignatureAlgorithm::Dilithium),
+            KeyTypes::MlDsa87 | KeyTypes::MlDsa65 | KeyTypes::MlDsa44 => Some(SignatureAlgorithm::MlDsa),
+            KeyTypes::SlhDsaSha2_128s | KeyTypes::SlhDsaSha2_128f | KeyTypes::SlhDsaSha2_192s
+                | KeyTypes::SlhDsaSha2_192f | KeyTypes::SlhDsaSha2_256s | KeyTypes::SlhDsaSha2_256f => Some(SignatureAlgorithm::SlhDsa),
+            _ => None,
+        }
+    }
+
+    /// SLH-DSA has more parameter sets than a size can tell apart, so its keys name them directly.
+    fn slh_dsa_parameters(&self) -> Option<slh_dsa::ParameterSet> {
+        match self {
+            KeyTypes::SlhDsaSha2_128s => Some(slh_dsa::ParameterSet::Sha2_128s),
+            KeyTypes::SlhDsaSha2_128f => Some(slh_dsa::ParameterSet::Sha2_128f),
+            KeyTypes::SlhDsaSha2_192s => Some(slh_dsa::ParameterSet::Sha2_192s),
+            KeyTypes::SlhDsaSha2_192f => Some(slh_dsa::ParameterSet::Sha2_192f),
+            KeyTypes::SlhDsaSha2_256s => Some(slh_dsa::ParameterSet::Sha2_256s),
+            KeyTypes::SlhDsaSha2_256f => Some(slh_dsa::ParameterSet::Sha2_256f),
+            _ => None,
+        }
+    }
+
+    /// Which specification the key encoding follows. The round 3 submissions and the
+    /// FIPS 203/204 standards use different encodings and are not interoperable.
+    fn format(&self) -> &'static str {
+        match self {
+            KeyTypes::Kyber1024 | KeyTypes::Kyber768 | KeyTypes::Kyber512 =>
+                "CRYSTALS-Kyber round 3 (pre-standard, not interoperable with FIPS 203 ML-KEM)",
+            KeyTypes::X25519Kyber1024 | KeyTypes::X25519Kyber768 | KeyTypes::X25519Kyber512 =>
+                "X25519 + CRYSTALS-Kyber round 3 hybrid (pre-standard Kyber)",
+            KeyTypes::Dilithium5 | KeyTypes::Dilithium3 | KeyTypes::Dilithium2 =>
+                "CRYSTALS-Dilithium round 3 (pre-standard, not interoperable with FIPS 204 ML-DSA)",
+            KeyTypes::Ed25519Dilithium5 | KeyTypes::Ed25519Dilithium3 | KeyTypes::Ed25519Dilithium2 =>
+                "Ed25519 + CRYSTALS-Dilithium round 3 composite (pre-standard Dilithium)",
+            KeyTypes::Falcon1024 | KeyTypes::Falcon512 => "Falcon round 3 (FIPS 206 FN-DSA is not final yet)",
+            KeyTypes::Ed25519Falcon1024 | KeyTypes::Ed25519Falcon512 => "Ed25519 + Falcon round 3 composite",
+            KeyTypes::MlKem1024 | KeyTypes::MlKem768 | KeyTypes::MlKem512 => "FIPS 203 ML-KEM (final standard)",
+            KeyTypes::MlDsa87 | KeyTypes::MlDsa65 | KeyTypes::MlDsa44 => "FIPS 204 ML-DSA (final standard)",
+            KeyTypes::SlhDsaSha2_128s | KeyTypes::SlhDsaSha2_128f | KeyTypes::SlhDsaSha2_192s
+                | KeyTypes::SlhDsaSha2_192f | KeyTypes::SlhDsaSha2_256s | KeyTypes::SlhDsaSha2_256f => "FIPS 205 SLH-DSA, stateless hash-based (final standard)",
+        }
+    }
+
+    /// Length of a detached signature, for algorithms whose signatures have a fixed size.
+    fn signature_len(&self) -> Option<usize> {
+        match self {
+            KeyTypes::Dilithium5 => Some(4595),
+            KeyTypes::Dilithium3 => Some(3293),
+            KeyTypes::Dilithium2 => Some(2420),
+            KeyTypes::Ed25519Dilithium5 => KeyTypes::Dilithium5.signature_len().map(composite::signature_len),
+            KeyTypes::Ed25519Dilithium3 => KeyTypes::Dilithium3.signature_len().map(composite::signature_len),
+            KeyTypes::Ed25519Dilithium2 => KeyTypes::Dilithium2.signature_len().map(composite::signature_len),
+            KeyTypes::MlDsa87 | KeyTypes::MlDsa65 | KeyTypes::MlDsa44 => ml_dsa::signature_len(self.key_size()),
+            _ => self.slh_dsa_parameters().map(slh_dsa::signature_len),
         }
     }
 }
@@ -101,7 +410,7 @@ impl fmt::Display for SignatureType {
 }
 
 impl SignatureType {
-    fn from_str(input: &str) -> Result<Self, CryptError> {
+    fn from_str(input: &str) -> Result<Self, CryptGuardError> {
         match input.to_lowercase().as_str() {
             "sign" => Ok(SignatureType::SignedData),
             "signed" => Ok(SignatureType::SignedData),
@@ -113,12 +422,13 @@ impl SignatureType {
             "detached_signature" => Ok(SignatureType::Detached),
             "detachedsign" => Ok(SignatureType::Detached),
             "detached_sign" => Ok(SignatureType::Detached),
-            _ => Err(CryptError::new(format!("Invalid algorithm: {}", input).as_str())),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid algorithm: {}", input))),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 enum SymmetricAlgorithm {
     AES,
     AES_GCM_SIV,
@@ -144,22 +454,45 @@ impl fmt::Display for SymmetricAlgorithm {
 }
 
 impl SymmetricAlgorithm {
-    fn from_str(input: &str) -> Result<Self, CryptError> {
+    fn from_str(input: &str) -> Result<Self, CryptGuardError> {
         match input.to_lowercase().as_str() {
             "aes" => Ok(SymmetricAlgorithm::AES),
             "aes_gcm_siv" => Ok(SymmetricAlgorithm::AES_GCM_SIV),
             "aes_ctr" => Ok(SymmetricAlgorithm::AES_CTR),
             "xchacha20" => Ok(SymmetricAlgorithm::XChaCha20),
             "xchacha20poly1305" => Ok(SymmetricAlgorithm::XChaCha20Poly1305),
-            _ => Err(CryptError::new(format!("Invalid algorithm: {}", input).as_str())),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid algorithm: {}", input))),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            SymmetricAlgorithm::AES => 1,
+            SymmetricAlgorithm::AES_GCM_SIV => 2,
+            SymmetricAlgorithm::AES_CTR => 3,
+            SymmetricAlgorithm::XChaCha20 => 4,
+            SymmetricAlgorithm::XChaCha20Poly1305 => 5,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, CryptGuardError> {
+        match id {
+            1 => Ok(SymmetricAlgorithm::AES),
+            2 => Ok(SymmetricAlgorithm::AES_GCM_SIV),
+            3 => Ok(SymmetricAlgorithm::AES_CTR),
+            4 => Ok(SymmetricAlgorithm::XChaCha20),
+            5 => Ok(SymmetricAlgorithm::XChaCha20Poly1305),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid algorithm identifier: {}", id))),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum SignatureAlgorithm {
     Falcon,
     Dilithium,
+    MlDsa,
+    SlhDsa,
 }
 
 impl fmt::Display for SignatureAlgorithm {
@@ -170,37 +503,44 @@ impl fmt::Display for SignatureAlgorithm {
             match self {
                 SignatureAlgorithm::Falcon => "falcon",
                 SignatureAlgorithm::Dilithium => "dilithium",
+                SignatureAlgorithm::MlDsa => "ml-dsa",
+                SignatureAlgorithm::SlhDsa => "slh-dsa",
             }
         )
     }
 }
 
 impl SignatureAlgorithm {
-    fn from_str(input: &str) -> Result<Self, CryptError> {
+    fn from_str(input: &str) -> Result<Self, CryptGuardError> {
         match input.to_lowercase().as_str() {
             "falcon" => Ok(SignatureAlgorithm::Falcon),
             "dilithium" => Ok(SignatureAlgorithm::Dilithium),
-            _ => Err(CryptError::new(format!("Invalid algorithm: {}", input).as_str())),
+            "ml-dsa" | "ml_dsa" | "mldsa" => Ok(SignatureAlgorithm::MlDsa),
+            "slh-dsa" | "slh_dsa" | "slhdsa" | "sphincs" | "sphincs+" => Ok(SignatureAlgorithm::SlhDsa),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid algorithm: {}", input))),
         }
     }
 }
 
-fn is_path(input: &str) -> Result<PathBuf, &str> {
-    if Path::new(input).exists() {
-        Ok(PathBuf::from(input))
-    } else {
-        Err("Not a valid path")
-    }
-}
-
-fn applet_commands() -> [Command; 2] {
+fn applet_commands() -> [Command; 4] {
     [
         Command::new("detached")
             .about("Verify a detached signature")
             .arg(
                 arg!(-i --input <INPUT>)
                     .required(true)
-                    .help("Path to the input file or message"),
+                    .help("Path to the input file, or - for stdin; with --message, the message itself"),
+            )
+            .arg(
+                arg!(-m --message)
+                    .action(ArgAction::SetTrue)
+                    .help("Indicates that the input is the message string, as passed to sign -m, rather than a file"),
+            )
+            .arg(
+                arg!(--file)
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("message")
+                    .help("Indicates that the input is a file; this is the default"),
             )
             .arg(
                 arg!(-s --signature <SIGNATURE>)
@@ -212,56 +552,250 @@ fn applet_commands() -> [Command; 2] {
                 arg!(-k --key <KEY>)
                     .required(true)
                     .value_parser(clap::value_parser!(PathBuf))
-                    .help("Public key for verification"),
+                    .help("Public key for verification, as a file or a keyring name, label or fingerprint prefix"),
             )
             .arg(
-                arg!(-K --keysize <KEYSIZE>)
-                    .required(true)
-                    .value_parser(clap::value_parser!(usize))
-                    .help("Size of the key in bits"),
+                arg!(-a --algorithm <ALGORITHM>)
+                    .help("Expected verification algorithm (falcon, dilithium, ml-dsa or slh-dsa); inferred from the key if omitted"),
             )
             .arg(
-                arg!(-a --algorithm <ALGORITHM>)
-                    .required(true)
-                    .help("Specify the verification algorithm (e.g., falcon1024, dilithium5)"),
+                arg!(--context <CONTEXT>)
+                    .help("Require a signature packet made for this context"),
             ),
         Command::new("signed")
             .about("Verify a signed message or file")
             .arg(
                 arg!(-i --input <INPUT>)
                     .required(true)
-                    .value_parser(clap::value_parser!(PathBuf))
-                    .help("Path to the signed input file or message"),
+                    .help("Path to the signed input file, or - for stdin; with --message, the signed data itself"),
+            )
+            .arg(
+                arg!(-m --message)
+                    .action(ArgAction::SetTrue)
+                    .help("Indicates that the input is the signed data as armored, Base64 or hex text, such as sign -m --armor prints, rather than a file"),
+            )
+            .arg(
+                arg!(--file)
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("message")
+                    .help("Indicates that the input is a file; this is the default"),
             )
             .arg(
                 arg!(-o --output <OUTPUT>)
                     .required(true)
                     .value_parser(clap::value_parser!(PathBuf))
-                    .help("Path to save the output"),
+                    .help("Path to save the output, or - for stdout"),
             )
             .arg(
                 arg!(-k --key <KEY>)
                     .required(true)
                     .value_parser(clap::value_parser!(PathBuf))
-                    .help("Public key for verification"),
+                    .help("Public key for verification, as a file or a keyring name, label or fingerprint prefix"),
+            )
+            .arg(
+                arg!(-a --algorithm <ALGORITHM>)
+                    .help("Expected verification algorithm (falcon, dilithium, ml-dsa or slh-dsa); inferred from the key if omitted"),
+            )
+            .arg(
+                arg!(--context <CONTEXT>)
+                    .help("Require a signature packet made for this context"),
+            ),
+        Command::new("cleartext")
+            .about("Verify a cleartext-signed text and print it")
+            .arg(
+                arg!(-i --input <INPUT>)
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Path to the cleartext-signed file, or - for stdin"),
+            )
+            .arg(
+                arg!(-o --output <OUTPUT>)
+                    .default_value(stdio::STDIO)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Path to save the verified text; defaults to stdout"),
             )
             .arg(
-                arg!(-K --keysize <KEYSIZE>)
+                arg!(-k --key <KEY>)
                     .required(true)
-                    .value_parser(clap::value_parser!(usize))
-                    .help("Size of the key in bits"),
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Public key for verification, as a file or a keyring name, label or fingerprint prefix"),
             )
             .arg(
                 arg!(-a --algorithm <ALGORITHM>)
+                    .help("Expected verification algorithm (falcon, dilithium, ml-dsa or slh-dsa); inferred from the key if omitted"),
+            )
+            .arg(
+                arg!(--context <CONTEXT>)
+                    .help("Require a signature made for this context"),
+            ),
+        Command::new("manifest")
+            .about("Verify the detached signatures listed in a manifest, or a signed SHA256SUMS manifest, in parallel")
+            .arg(
+                arg!(<MANIFEST>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Manifest with one FILE SIGNATURE [KEY] line per entry, or a SHA256SUMS manifest from sign --manifest, or - for stdin"),
+            )
+            .arg(
+                arg!(-k --key <KEY>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Public key for entries that don't name their own, or the key that signed a SHA256SUMS manifest"),
+            )
+            .arg(
+                arg!(-s --signature <SIGNATURE>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Signature of a SHA256SUMS manifest; defaults to <MANIFEST>.sig"),
+            )
+            .arg(
+                arg!(--root <DIR>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Directory the paths of a SHA256SUMS manifest are relative to; defaults to the manifest's directory"),
+            ),
+    ]
+}
+
+/// Mutually exclusive ways to supply the passphrase of `encrypt` and `decrypt`.
+fn passphrase_args() -> [Arg; 4] {
+    [
+        arg!(-p --passphrase <PASSPHRASE>)
+            .group("passphrase-source")
+            .help("Passphrase given directly; visible in shell history and the process list, prefer the options below"),
+        arg!(--"passphrase-file" <FILE>)
+            .group("passphrase-source")
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Read the passphrase from the first line of this file"),
+        arg!(--"passphrase-env" <VAR>)
+            .group("passphrase-source")
+            .help("Read the passphrase from this environment variable"),
+        arg!(--"passphrase-fd" <FD>)
+            .group("passphrase-source")
+            .value_parser(clap::value_parser!(i32))
+            .help("Read the passphrase from the first line of this file descriptor"),
+    ]
+}
+
+fn armor_arg() -> Arg {
+    arg!(--armor)
+        .action(ArgAction::SetTrue)
+        .help("Write Base64 text with BEGIN/END lines and a CRC-24 checksum instead of binary")
+}
+
+/// Options for unlocking a passphrase-protected secret key; without them the passphrase is prompted for.
+fn key_passphrase_args() -> [Arg; 2] {
+    [
+        arg!(--"key-passphrase-env" <VAR>)
+            .conflicts_with("key-passphrase-fd")
+            .help("Read the secret key passphrase from this environment variable"),
+        arg!(--"key-passphrase-fd" <FD>)
+            .value_parser(clap::value_parser!(i32))
+            .help("Read the secret key passphrase from the first line of this file descriptor"),
+    ]
+}
+
+fn key_commands() -> [Command; 7] {
+    [
+        Command::new("info")
+            .about("Show algorithm, security level, role, size and fingerprint of a key file")
+            .arg(
+                arg!(<FILE>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Key file, or a keyring name, label or fingerprint prefix"),
+            ),
+        Command::new("import")
+            .about("Add a public or secret key file to the keyring")
+            .arg(
+                arg!(<FILE>)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Key file to import"),
+            )
+            .arg(
+                arg!(-n --name <NAME>)
+                    .help("Name of the keyring entry; defaults to the file name without extension"),
+            )
+            .arg(
+                arg!(-l --label <LABEL>)
+                    .help("Email-like label to find the entry by, e.g. alice@example.com"),
+            ),
+        Command::new("export")
+            .about("Write a key from the keyring to a file")
+            .arg(
+                arg!(<KEY>)
+                    .help("Name, label or fingerprint prefix of the keyring entry"),
+            )
+            .arg(
+                arg!(-o --output <OUTPUT>)
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Path to save the key, or - for stdout"),
+            )
+            .arg(
+                arg!(--secret)
+                    .action(ArgAction::SetTrue)
+                    .help("Export the secret key instead of the public key"),
+            )
+            .arg(armor_arg()),
+        Command::new("list")
+            .about("List the keys in the keyring"),
+        Command::new("delete")
+            .about("Remove an entry from the keyring")
+            .arg(
+                arg!(<NAME>)
+                    .help("Exact name of the keyring entry"),
+            )
+            .arg(
+                arg!(--force)
+                    .action(ArgAction::SetTrue)
+                    .help("Also delete entries that hold a secret key"),
+            ),
+        Command::new("public")
+            .about("Extract the public key from a secret key file")
+            .arg(
+                arg!(--from <KEY>)
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Secret key file"),
+            )
+            .arg(
+                arg!(-o --output <OUTPUT>)
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Path to save the public key, or - for stdout"),
+            )
+            .arg(armor_arg())
+            .args(key_passphrase_args()),
+        Command::new("change-passphrase")
+            .about("Set, change or remove the passphrase protecting a secret key")
+            .arg(
+                arg!(-k --key <KEY>)
                     .required(true)
-                    .help("Specify the verification algorithm (e.g., falcon1024, dilithium5)"),
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Secret key file to update in place"),
+            )
+            .args(key_passphrase_args())
+            .arg(
+                arg!(--"new-passphrase-env" <VAR>)
+                    .conflicts_with_all(["new-passphrase-fd", "remove"])
+                    .help("Read the new passphrase from this environment variable"),
+            )
+            .arg(
+                arg!(--"new-passphrase-fd" <FD>)
+                    .value_parser(clap::value_parser!(i32))
+                    .conflicts_with("remove")
+                    .help("Read the new passphrase from the first line of this file descriptor"),
+            )
+            .arg(
+                arg!(--remove)
+                    .action(ArgAction::SetTrue)
+                    .help("Store the secret key unprotected"),
             ),
     ]
 }
 
 fn main() {
     let matches = build_cli().get_matches();
-    let _ = parse_cli(matches);
+    if let Err(error) = parse_cli(matches) {
+        eprintln!("{}", error);
+        std::process::exit(error.exit_code());
+    }
 }
 
 fn build_cli() -> Command {
@@ -271,6 +805,7 @@ fn build_cli() -> Command {
         .arg_required_else_help(true)
         .version("1.0")
         .author("mm29942 <mm29942@cryptguard.org>")
+        .after_help("Exit codes: 0 success, 1 cryptographic failure, 2 bad arguments, 3 I/O error, 4 authentication failure, 5 invalid signature")
 
         .subcommand(
             Command::new("keygen")
@@ -278,14 +813,31 @@ fn build_cli() -> Command {
                 .arg(
                     arg!(-a --algorithm <ALGORITHM>)
                         .required(true)
-                        .help("Specify the algorithm (e.g., kyber1024, falcon1024, dilithium5)"),
+                        .help("Specify the algorithm (e.g., ml-kem-768, ml-dsa-65, slh-dsa-sha2-128s, kyber1024, x25519kyber1024, falcon1024, dilithium5, ed25519dilithium5)"),
                 )
                 .arg(
                     arg!(-d --directory <DIR>)
-                        .required(true)
+                        .required_unless_present("name")
+                        .conflicts_with("name")
                         .value_parser(clap::value_parser!(PathBuf))
                         .help("Directory to save the keys"),
-                ),
+                )
+                .arg(
+                    arg!(-n --name <NAME>)
+                        .help("Add the key pair to the keyring under this name instead of writing it to a directory"),
+                )
+                .arg(
+                    arg!(-l --label <LABEL>)
+                        .requires("name")
+                        .help("Email-like label to find the keyring entry by, e.g. alice@example.com"),
+                )
+                .arg(
+                    arg!(--protect)
+                        .action(ArgAction::SetTrue)
+                        .help("Seal the secret key with a passphrase (Argon2id + XChaCha20Poly1305)"),
+                )
+                .arg(armor_arg())
+                .args(key_passphrase_args()),
         )
 
         .subcommand(
@@ -294,29 +846,40 @@ fn build_cli() -> Command {
                 .arg(
                     arg!(-i --input <INPUT>)
                         .required(true)
-                        .help("Path to the input file or message"),
+                        .help("Path to the input file, directory or message, or - for stdin"),
                 )
                 .arg(
                     arg!(-o --output <OUTPUT>)
-                        .required(true)
-                        .help("Path to save the encrypted output"),
+                        .required_unless_present("message")
+                        .help("Path to save the encrypted output, or - for stdout; with --message, defaults to Base64 on stdout"),
                 )
                 .arg(
                     arg!(-k --key <KEY>)
-                        .required(true)
+                        .required_unless_present("recipient")
+                        .conflicts_with("recipient")
                         .value_parser(clap::value_parser!(PathBuf))
-                        .help("Public key for encryption"),
+                        .help("Public key for encryption, as a file or a keyring name, label or fingerprint prefix"),
                 )
                 .arg(
-                    arg!(-K --keysize <KEYSIZE>)
-                        .required(true)
-                        .value_parser(clap::value_parser!(usize))
-                        .help("Size of the key in bits"),
+                    arg!(-r --recipient <KEY>)
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Public key (file or keyring reference) of a recipient; repeat to encrypt for several keys (xchacha20poly1305 or aes_gcm_siv)"),
+                )
+                .args(passphrase_args())
+                .arg(
+                    arg!(--"ask-passphrase")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("passphrase-source")
+                        .help("Prompt for a passphrase on the terminal, without echo and with confirmation"),
                 )
                 .arg(
-                    arg!(-p --passphrase <PASSPHRASE>)
-                        .help("Passphrase for encryption (optional)"),
+                    arg!(--"sign-with" <KEY>)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Falcon or Dilithium secret key (file or keyring reference) to sign the plaintext with before encrypting"),
                 )
+                .arg(armor_arg())
+                .args(key_passphrase_args())
                 .arg(
                     arg!(-a --algorithm <ALGORITHM>)
                         .required(true)
@@ -326,6 +889,23 @@ fn build_cli() -> Command {
                     arg!(-m --message)
                         .action(ArgAction::SetTrue)
                         .help("Indicates that the input is a message string rather than a file"),
+                )
+                .arg(
+                    arg!(--encoding <ENCODING>)
+                        .conflicts_with("armor")
+                        .help("Write the container as a single line of hex or base64 text, for passing it inline to decrypt --message"),
+                )
+                .arg(
+                    arg!(--stream)
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("message")
+                        .help("Encrypt in constant memory using chunked AEAD (xchacha20poly1305 or aes_gcm_siv)"),
+                )
+                .arg(
+                    arg!(--"chunk-size" <BYTES>)
+                        .value_parser(clap::value_parser!(u32).range(1..=stream::MAX_CHUNK_SIZE as i64))
+                        .default_value("65536")
+                        .help("Plaintext bytes per chunk in streaming mode"),
                 ),
         )
 
@@ -335,44 +915,31 @@ fn build_cli() -> Command {
                 .arg(
                     arg!(-i --input <INPUT>)
                         .required(true)
-                        .help("Path to the encrypted input file or message"),
+                        .help("Path to the encrypted container, or - for stdin; with --message, the ciphertext itself"),
                 )
                 .arg(
                     arg!(-o --output <OUTPUT>)
-                        .required(true)
-                        .help("Path to save the decrypted output"),
-                )
-                .arg(
-                    arg!(-k --key <KEY>)
-                        .required(true)
-                        .value_parser(clap::value_parser!(PathBuf))
-                        .help("Secret key for decryption"),
+                        .required_unless_present("message")
+                        .help("Path to save the decrypted output (a directory for encrypted directories), or - for stdout; defaults to stdout with --message"),
                 )
                 .arg(
-                    arg!(-K --keysize <KEYSIZE>)
-                        .required(true)
-                        .value_parser(clap::value_parser!(usize))
-                        .help("Size of the key in bits"),
+                    arg!(-m --message)
+                        .action(ArgAction::SetTrue)
+                        .help("Indicates that the input is inline hex, base64 or armored ciphertext rather than a file"),
                 )
                 .arg(
-                    arg!(-c --cipher <CIPHER>)
+                    arg!(-k --key <KEY>)
                         .required(true)
                         .value_parser(clap::value_parser!(PathBuf))
-                        .help("Ciphertext for decryption"),
-                )
-                .arg(
-                    arg!(-p --passphrase <PASSPHRASE>)
-                        .help("Passphrase for decryption (if used during encryption)"),
+                        .help("Secret key for decryption, as a file or a keyring name, label or fingerprint prefix"),
                 )
+                .args(passphrase_args())
                 .arg(
-                    arg!(-a --algorithm <ALGORITHM>)
-                        .required(true)
-                        .help("Specify the decryption algorithm (e.g., aes, xchacha20)"),
+                    arg!(--"verify-with" <KEY>)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Public key (file or keyring reference) the embedded signature must verify against; decryption fails otherwise"),
                 )
-                .arg(
-                    arg!(-n --nonce <NONCE>)
-                        .help("Nonce for decryption (required for xchacha20)"),
-                ),
+                .args(key_passphrase_args()),
         )
 
         .subcommand(
@@ -380,42 +947,81 @@ fn build_cli() -> Command {
                 .about("Sign a message or file")
                 .arg(
                     arg!(-i --input <INPUT>)
-                        .required(true)
-                        .help("Path to the input file or message"),
+                        .required_unless_present("manifest")
+                        .help("Path to the input file or message, or - for stdin"),
                 )
                 .arg(
                     arg!(-o --output <OUTPUT>)
                         .required(true)
-                        .help("Path to save the signature"),
+                        .help("Path to save the signature, or - for stdout; with --manifest, the path of the manifest"),
                 )
                 .arg(
-                    arg!(-k --key <KEY>)
-                        .required(true)
+                    arg!(--manifest <DIR>)
+                        .conflicts_with_all(["input", "type", "message"])
                         .value_parser(clap::value_parser!(PathBuf))
-                        .help("Secret key for signing"),
+                        .help("Hash every file below DIR into a SHA256SUMS manifest and sign the manifest once, writing the signature next to it as <OUTPUT>.sig"),
                 )
                 .arg(
-                    arg!(-K --keysize <KEYSIZE>)
+                    arg!(-k --key <KEY>)
                         .required(true)
-                        .value_parser(clap::value_parser!(usize))
-                        .help("Size of the key in bits"),
+                        .short_alias('u')
+                        .visible_alias("local-user")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Secret key for signing: a file, or a keyring name, label or fingerprint prefix"),
                 )
                 .arg(
                     arg!(-a --algorithm <ALGORITHM>)
-                        .required(true)
-                        .help("Specify the signing algorithm (e.g., falcon, dilithium)"),
+                        .help("Expected signing algorithm (falcon, dilithium, ml-dsa or slh-dsa); inferred from the key if omitted"),
                 )
                 .arg(
                     arg!(-t --type <ALGORITHM>)
-                        .required(true)
+                        .required_unless_present_any(["manifest", "clearsign"])
                         .help("Specify the signing variant (detached or signeddata)"),
                 )
                 .arg(
                     arg!(-m --message)
                         .action(ArgAction::SetTrue)
                         .help("Indicates that the input is a message string rather than a file"),
+                )
+                .arg(
+                    arg!(--clearsign)
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["type", "manifest", "armor"])
+                        .help("Write the text in readable form followed by an armored signature block; lines are signed with normalized line endings"),
+                )
+                .arg(
+                    arg!(--packet)
+                        .action(ArgAction::SetTrue)
+                        .visible_alias("prehash")
+                        .help("Write a signature packet recording the creation time, signer fingerprint and context; implied by --context and --expires. The input is streamed through SHA3-512 and only the digest is signed"),
+                )
+                .arg(
+                    arg!(--context <CONTEXT>)
+                        .help("What the signature is for, such as release or backup; verify --context only accepts a matching packet"),
+                )
+                .arg(
+                    arg!(--expires <LIFETIME>)
+                        .help("Let the signature expire after LIFETIME, e.g. 3600, 12h, 30d or 1y"),
+                )
+                .arg(armor_arg())
+                .args(key_passphrase_args()),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Show which algorithm and encoding a key, container or signature uses")
+                .arg(
+                    arg!(<FILE>)
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Key file, encrypted container or detached signature, or - for stdin"),
                 ),
         )
+        .subcommand(
+            Command::new("key")
+                .about("Manage key files")
+                .arg_required_else_help(true)
+                .subcommand_value_name("OPERATION")
+                .subcommands(key_commands()),
+        )
         .subcommand(
             Command::new("verify")
                 .about("Verify a signature")
@@ -426,529 +1032,1520 @@ fn build_cli() -> Command {
         )
 }
 
-fn create_parent_dir(path: &Path) -> Result<(), std::io::Error> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+fn write_container_file(path: &Path, header: &Header, payload: &[u8], armored: bool, encoding: Option<Encoding>) -> Result<(), CryptGuardError> {
+    if let Some(encoding) = encoding {
+        let mut container = Vec::new();
+        container::write_container(&mut container, header, payload)?;
+        return Ok(stdio::write_output(path, format!("{}\n", encoding.encode(&container)).as_bytes())?);
+    }
+
+    let mut output = stdio::create_output(path)?;
+    match armored {
+        true => {
+            let mut writer = ArmorWriter::new(&mut output, armor::ENCRYPTED_MESSAGE)?;
+            container::write_container(&mut writer, header, payload)?;
+            writer.finish()?;
+        },
+        false => container::write_container(&mut output, header, payload)?,
     }
+    output.flush()?;
     Ok(())
 }
 
-fn parse_cli(matches: clap::ArgMatches) -> Result<(), CryptError> {
-    match matches.subcommand() {
-        Some(("keygen", sub_matches)) => {
-            use ::crypt_guard::KDF::*;
-            let algorithm = sub_matches.get_one::<String>("algorithm").expect("required");
-            let directory = sub_matches.get_one::<PathBuf>("directory").expect("required");
-            println!("Generating key pair with algorithm {} in directory {:?}", algorithm, directory);
-
-            match KeyTypes::from_str(algorithm.as_str()) {
-                Ok(keytype) => {
-                    let (public, secret) = match keytype {
-                        KeyTypes::Falcon1024 => Ok(FalconKeypair!(1024)),
-                        KeyTypes::Falcon512 => Ok(FalconKeypair!(512)),
-                        KeyTypes::Kyber1024 => Ok(KyberKeypair!(1024)),
-                        KeyTypes::Kyber768 => Ok(KyberKeypair!(768)),
-                        KeyTypes::Kyber512 => Ok(KyberKeypair!(512)),
-                        KeyTypes::Dilithium5 => Ok(DilithiumKeypair!(5)),
-                        KeyTypes::Dilithium3 => Ok(DilithiumKeypair!(3)),
-                        KeyTypes::Dilithium2 => Ok(DilithiumKeypair!(2)),
-                        _ => Err(CryptError::new("Keygen failed!"))
-                    }.expect("Keygen failed!");
-
-                    let dir_name = directory.file_name().expect("Directory should have a name").to_str().expect("Invalid directory name");
-                    let public_key_path = directory.join(format!("{}.pub", dir_name));
-                    let secret_key_path = directory.join(format!("{}.sec", dir_name));
-
-                    // Create the directory if it does not exist
-                    std::fs::create_dir_all(directory).expect("Failed to create directories");
-
-                    // Save the public key
-                    {
-                        let mut public_file = File::create(&public_key_path).expect("Failed to create public key file");
-                        public_file.write_all(&public).expect("Failed to write public key");
-                    }
-
-                    // Save the secret key
-                    {
-                        let mut secret_file = File::create(&secret_key_path).expect("Failed to create secret key file");
-                        secret_file.write_all(&secret).expect("Failed to write secret key");
-                    }
-
-                    println!("Keys generated and saved to {} and {}", public_key_path.display(), secret_key_path.display());
-                    Ok(())
-                },
-                Err(e) => {
-                    Err(CryptError::new(format!("Error: {}", e).as_str()))
-                }
-            }
-        }
-        Some(("encrypt", sub_matches)) => {
-            let input = sub_matches.get_one::<String>("input").expect("required");
-            let output = sub_matches.get_one::<String>("output").expect("required");
-            let mut output_path = PathBuf::from(output);
-
-            let key = sub_matches.get_one::<PathBuf>("key").expect("required");
-            let key_size = sub_matches.get_one::<usize>("keysize").expect("required");
-            let passphrase = sub_matches.get_one::<String>("passphrase");
-            let algorithm_str = sub_matches.get_one::<String>("algorithm").expect("required");
-            let algorithm = SymmetricAlgorithm::from_str(algorithm_str).unwrap();
-
-            match sub_matches.get_flag("message") {
-                true => {
-                    println!("Encrypting {} to {} using {} with algorithm {} and is message: {}", input, output, key.display(), algorithm, sub_matches.get_flag("message"));
-                    let (encrypted, cipher) = match key_size {
-                        1024 => Encryption!(fs::read(key).unwrap(), 1024, input.clone().as_bytes().to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                        768 => Encryption!(fs::read(key).unwrap(), 768, input.clone().as_bytes().to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                        512 => Encryption!(fs::read(key).unwrap(), 512, input.clone().as_bytes().to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                        _ => Err(CryptError::new("Encryption failed!"))
-                    }.expect("Encryption failed!");
-
-                    // Create the parent directory if it does not exist
-                    create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                    let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                    output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                    let _ = output_path.set_extension("ct");
-                    create_parent_dir(&output_path).expect("Failed to create parent directory");
-                    let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                    output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                    println!("Finished encryption of the message, it's saved at: {}", output_path.display());
-                    Ok(())
-                },
-                false => {
-                    let input_path = PathBuf::from(input);
-                    let mut output_path = PathBuf::from(output);
-
-                    match algorithm {
-                        SymmetricAlgorithm::AES => {
-                            let input_data = fs::read(input).unwrap();
-                            let (encrypted, cipher) = match key_size {
-                                1024 => Encryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                                768 => Encryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                                512 => Encryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES),
-                                _ => Err(CryptError::new("Encryption failed!"))
-                            }.expect("Encryption failed!");
-
-                            // Create the parent directory if it does not exist
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                            let _ = output_path.set_extension("ct");
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                            println!("Encrypting {} to {} using {} with algorithm {} has finished, the ciphertext is of size {}", input_path.display(), output_path.display(), key.display(), algorithm, cipher.len());
-                        },
-                        SymmetricAlgorithm::AES_GCM_SIV => {
-                            let input_data = fs::read(input).unwrap();
-                            let (encrypted, cipher, nonce) = match key_size {
-                                1024 => Encryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_GCM_SIV),
-                                768 => Encryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_GCM_SIV),
-                                512 => Encryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_GCM_SIV),
-                                _ => todo!(),
-                            };
-
-                            // Create the parent directory if it does not exist
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                            let _ = output_path.set_extension("ct");
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                            println!("Encrypting {} to {} using {} with algorithm {} has finished, the ciphertext is of size {}", input_path.display(), output_path.display(), key.display(), algorithm, cipher.len());
-                        },
-                        SymmetricAlgorithm::AES_CTR => {
-                            let input_data = fs::read(input).unwrap();
-                            let (encrypted, cipher, nonce) = match key_size {
-                                1024 => Encryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_CTR),
-                                768 => Encryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_CTR),
-                                512 => Encryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), AES_CTR),
-                                _ => todo!(),
-                            };
-
-                            // Create the parent directory if it does not exist
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                            let _ = output_path.set_extension("ct");
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                            println!("Encrypting {} to {} using {} with algorithm {} has finished, the ciphertext is of size {}", input_path.display(), output_path.display(), key.display(), algorithm, cipher.len());
-                        },
-                        SymmetricAlgorithm::XChaCha20 => {
-                            let input_data = fs::read(input).unwrap();
-                            let (encrypted, cipher, nonce) = match key_size {
-                                1024 => Encryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20),
-                                768 => Encryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20),
-                                512 => Encryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20),
-                                _ => todo!(),
-                            };
-
-                            // Create the parent directory if it does not exist
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                            let _ = output_path.set_extension("ct");
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                            println!("Encrypting {} to {} using {} with algorithm {} has finished, the ciphertext is of size {}. Note down the nonce: {}", input_path.display(), output_path.display(), key.display(), algorithm, cipher.len(), nonce);
-                        },
-                        SymmetricAlgorithm::XChaCha20Poly1305 => {
-                            let input_data = fs::read(input).unwrap();
-                            let (encrypted, cipher, nonce) = match key_size {
-                                1024 => Encryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20Poly1305),
-                                768 => Encryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20Poly1305),
-                                512 => Encryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), XChaCha20Poly1305),
-                                _ => todo!(),
-                            };
-
-                            // Create the parent directory if it does not exist
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&encrypted).expect("Failed to write encrypted data");
-
-                            let _ = output_path.set_extension("ct");
-                            create_parent_dir(&output_path).expect("Failed to create parent directory");
-                            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-                            output_file.write_all(&cipher).expect("Failed to write encrypted data");
-
-                            println!("Encrypting {} to {} using {} with algorithm {} has finished, the ciphertext is of size {}. Note down the nonce: {}", input_path.display(), output_path.display(), key.display(), algorithm, cipher.len(), nonce);
-                        },
-                        _ => todo!(),
-                    };
-                    Ok(())
-                }
-            }
-        }
-        Some(("decrypt", sub_matches)) => {
-            let input = sub_matches.get_one::<String>("input").expect("required");
-            let output = sub_matches.get_one::<String>("output").expect("required");
-
-            let key = sub_matches.get_one::<PathBuf>("key").expect("required");
-            let key_size = sub_matches.get_one::<usize>("keysize").expect("required");
-            let cipher_path = sub_matches.get_one::<PathBuf>("cipher").expect("required");
-
-            let passphrase = sub_matches.get_one::<String>("passphrase");
-            let algorithm_str = sub_matches.get_one::<String>("algorithm").expect("required");
-            let algorithm: SymmetricAlgorithm = SymmetricAlgorithm ::from_str(algorithm_str).unwrap();
-
-            let nonce = sub_matches.get_one::<String>("nonce");
-
-            println!("Decrypting {} to {} using {} with algorithm {}", input, output, key.display(), algorithm);
-
-            let input_path = PathBuf::from(input);
-            let input_data = fs::read(input).unwrap();
-            let output_path = PathBuf::from(output);
-
-            let decrypted = match algorithm {
-                SymmetricAlgorithm::AES => match key_size {
-                    1024 => Decryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), AES),
-                    768 => Decryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), AES),
-                    512 => Decryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), AES),
-                    _ => Err(CryptError::new("Decryption failed!"))
-                },
-                SymmetricAlgorithm::AES_GCM_SIV => match key_size {
-                    1024 => Decryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_GCM_SIV),
-                    768 => Decryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_GCM_SIV),
-                    512 => Decryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_GCM_SIV),
-                    _ => Err(CryptError::new("Decryption failed!"))
-                },
-                SymmetricAlgorithm::AES_CTR => match key_size {
-                    1024 => Decryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_CTR),
-                    768 => Decryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_CTR),
-                    512 => Decryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.unwrap().to_string()), AES_CTR),
-                    _ => Err(CryptError::new("Decryption failed!"))
-                },
-                SymmetricAlgorithm::XChaCha20 => {
-                    let nonce = nonce.expect("Nonce is required for XChaCha20");
-                    match key_size {
-                        1024 => Decryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20),
-                        768 => Decryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20),
-                        512 => Decryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20),
-                        _ => Err(CryptError::new("Decryption failed!"))
-                    }
-                },
-                SymmetricAlgorithm::XChaCha20Poly1305 => {
-                    let nonce = nonce.expect("Nonce is required for XChaCha20");
-                    match key_size {
-                        1024 => Decryption!(fs::read(key).unwrap(), 1024, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20Poly1305),
-                        768 => Decryption!(fs::read(key).unwrap(), 768, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20Poly1305),
-                        512 => Decryption!(fs::read(key).unwrap(), 512, input_data.to_owned(), passphrase.clone().unwrap().as_str(), fs::read(cipher_path).unwrap(), Some(nonce.to_string()), XChaCha20Poly1305),
-                        _ => Err(CryptError::new("Decryption failed!"))
-                    }
-                },
-            }.expect("Decryption failed!");
-
-            // Create the parent directory if it does not exist
-            create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-            let mut output_file = File::create(&output_path).expect("Failed to create output file");
-            output_file.write_all(&decrypted).expect("Failed to write decrypted data");
-
-            println!("Finished decryption of: {}", input_path.display());
-            Ok(())
-        }
-        Some(("sign", sub_matches)) => {
-            use ::crypt_guard::KDF::*;
-            let input = sub_matches.get_one::<String>("input").expect("required");
-            let output = sub_matches.get_one::<String>("output").expect("required");
-            let output_path = PathBuf::from(output);
-
-            let key = sub_matches.get_one::<PathBuf>("key").expect("required");
-
-            let key_size = sub_matches.get_one::<usize>("keysize").expect("required");
-            let algorithm_str = sub_matches.get_one::<String>("algorithm").expect("required");
-            let algorithm = SignatureAlgorithm::from_str(algorithm_str).expect("");
-
-            let type_str = sub_matches.get_one::<String>("type").expect("required");
-            let r#type = SignatureType::from_str(type_str).unwrap();
-
-            let signature = match sub_matches.get_flag("message") {
-                true => {
-                    let key_data = fs::read(key).unwrap();
-
-                    match r#type {
-                        SignatureType::SignedData => {
-                            match algorithm {
-                                SignatureAlgorithm::Falcon => {
-                                    match key_size {
-                                        1024 => Signature!(Falcon, key_data.to_owned(), 1024, input.as_bytes().to_owned(), Message),
-                                        512 => Signature!(Falcon, key_data.to_owned(), 512, input.as_bytes().to_owned(), Message),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                SignatureAlgorithm::Dilithium => {
-                                    match key_size {
-                                        5 => Signature!(Dilithium, key_data.to_owned(), 5, input.as_bytes().to_owned(), Message),
-                                        3 => Signature!(Dilithium, key_data.to_owned(), 3, input.as_bytes().to_owned(), Message),
-                                        2 => Signature!(Dilithium, key_data.to_owned(), 2, input.as_bytes().to_owned(), Message),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                _ => return Err(CryptError::new("Signing Failed!")),
-                            }
-                        },
-                        SignatureType::Detached => {
-                            match algorithm {
-                                SignatureAlgorithm::Falcon => {
-                                    match key_size {
-                                        1024 => Signature!(Falcon, key_data.to_owned(), 1024, input.as_bytes().to_owned(), Detached),
-                                        512 => Signature!(Falcon, key_data.to_owned(), 512, input.as_bytes().to_owned(), Detached),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                SignatureAlgorithm::Dilithium => {
-                                    match key_size {
-                                        5 => Signature!(Dilithium, key_data.to_owned(), 5, input.as_bytes().to_owned(), Detached),
-                                        3 => Signature!(Dilithium, key_data.to_owned(), 3, input.as_bytes().to_owned(), Detached),
-                                        2 => Signature!(Dilithium, key_data.to_owned(), 2, input.as_bytes().to_owned(), Detached),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                _ => return Err(CryptError::new("Signing Failed!")),
-                            }
-                        },
-                    }
-                },
-                false => {
-                    let input_data = &fs::read(input).unwrap();
-                    let key_data = &fs::read(key).unwrap();
-
-                    match r#type {
-                        SignatureType::SignedData => {
-                            match algorithm {
-                                SignatureAlgorithm::Falcon => {
-                                    match key_size {
-                                        1024 => Signature!(Falcon, key_data.to_owned(), 1024, input_data.to_owned(), Message),
-                                        512 => Signature!(Falcon, key_data.to_owned(), 512, input_data.to_owned(), Message),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                SignatureAlgorithm::Dilithium => {
-                                    match key_size {
-                                        5 => Signature!(Dilithium, key_data.to_owned(), 5, input_data.to_owned(), Message),
-                                        3 => Signature!(Dilithium, key_data.to_owned(), 3, input_data.to_owned(), Message),
-                                        2 => Signature!(Dilithium, key_data.to_owned(), 2, input_data.to_owned(), Message),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                _ => return Err(CryptError::new("Signing Failed!")),
-                            }
-                        },
-                        SignatureType::Detached => {
-                            match algorithm {
-                                SignatureAlgorithm::Falcon => {
-                                    match key_size {
-                                        1024 => Signature!(Falcon, key_data.to_owned(), 1024, input_data.to_owned(), Detached),
-                                        512 => Signature!(Falcon, key_data.to_owned(), 512, input_data.to_owned(), Detached),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                SignatureAlgorithm::Dilithium => {
-                                    match key_size {
-                                        5 => Signature!(Dilithium, key_data.to_owned(), 5, input_data.to_owned(), Detached),
-                                        3 => Signature!(Dilithium, key_data.to_owned(), 3, input_data.to_owned(), Detached),
-                                        2 => Signature!(Dilithium, key_data.to_owned(), 2, input_data.to_owned(), Detached),
-                                        _ => return Err(CryptError::new("Signing Failed!")),
-                                    }
-                                },
-                                _ => return Err(CryptError::new("Signing Failed!")),
-                            }
-                        },
-                    }
-                }
-            };
-            match signature {
-                sig => {
-                    // Create the parent directory if it does not exist
-                    create_parent_dir(&output_path).expect("Failed to create parent directory");
-
-                    fs::write(output_path, sig).unwrap();
-                    println!("Signing {} to {} using {} with algorithm {}", input, output, key.display(), algorithm);
-                    Ok(())
-                },
-                _ => {
-                    return Err(CryptError::new("Signing Failed!"))
-                }
-            }
-        }
+/// Writes `header` with a fresh nonce prefix, followed by `reader` encrypted in chunks
+/// under a key derived from `secret` and the passphrase.
+fn encrypt_stream<R: Read, W: Write>(reader: &mut R, writer: &mut W, mut header: Header, secret: &[u8], passphrase: &str) -> Result<u64, CryptGuardError> {
+    if !stream::supports(&header.algorithm) {
+        return Err(CryptGuardError::ParseError(format!("--stream, --recipient, hybrid and ML-KEM keys require xchacha20poly1305 or aes_gcm_siv, not {}", header.algorithm)));
+    }
 
-        Some(("verify", sub_matches)) => {
-            use ::crypt_guard::KDF::*;
-            match sub_matches.subcommand() {
-                Some(("detached", cmd)) => {
-                    let input = cmd.get_one::<String>("input").expect("required");
-
-                    let input_data = match is_path(input) {
-                        Ok(input) => fs::read(input).unwrap(),
-                        Err(input) => input.as_bytes().to_owned(),
-                    };
-
-                    let key_size = cmd.get_one::<usize>("keysize").expect("required");
-
-                    let signature = cmd.get_one::<PathBuf>("signature").expect("required");
-                    let signature_data = fs::read(signature).unwrap();
-
-                    let key = cmd.get_one::<PathBuf>("key").expect("required");
-                    let key_data = fs::read(key).unwrap();
-
-                    let algorithm_str = cmd.get_one::<String>("algorithm").expect("required");
-                    let algorithm = SignatureAlgorithm::from_str(algorithm_str.as_str()).unwrap();
-
-                    match is_path(input) {
-                        Ok(input) => {
-                            println!(
-                                "Verifying detached signature for {} with signature {} using key {} with algorithm {}",
-                                input.display(), signature.display(), key.display(), algorithm
-                            );
-                        },
-                        Err(input) => {
-                            println!(
-                                "Verifying detached signature for {} with signature {} using key {} with algorithm {}",
-                                input, signature.display(), key.display(), algorithm
-                            );
-                        },
-                    };
-
-
-                    // Perform the verification
-                    let is_valid = match algorithm {
-                        SignatureAlgorithm::Falcon => {
-                            match key_size {
-                                1024 => Verify!(Falcon, key_data.to_owned().to_owned().to_owned(), 1024, signature_data.to_owned(), input_data.to_owned(), Detached),
-                                512 => Verify!(Falcon, key_data.to_owned().to_owned().to_owned(), 512, signature_data.to_owned(), input_data.to_owned(), Detached),
-                                _ => unreachable!(),
-                            }
-                        },
-                        SignatureAlgorithm::Dilithium => {
-                            match key_size {
-                                5 => Verify!(Dilithium, key_data.to_owned().to_owned(), 5, signature_data.to_owned(), input_data.to_owned(), Detached),
-                                3 => Verify!(Dilithium, key_data.to_owned().to_owned(), 3, signature_data.to_owned(), input_data.to_owned(), Detached),
-                                2 => Verify!(Dilithium, key_data.to_owned().to_owned(), 2, signature_data.to_owned(), input_data.to_owned(), Detached),
-                                _ => unreachable!(),
-                            }
-                        },
-                        _ => unreachable!(),
-                    };
-
-                    if is_valid {
-                        println!("Detached signature is valid.");
-                    } else {
-                        println!("Detached signature is invalid.");
-                    }
-
-                    Ok(())
-                },
-                Some(("signed", cmd)) => {
-                    let input = cmd.get_one::<PathBuf>("input").expect("required");
-                    let input_data = fs::read(input).unwrap();
-
-                    let output = cmd.get_one::<PathBuf>("output").expect("required");
-
-                    let _key = cmd.get_one::<PathBuf>("key").expect("required");
-                    let key_size = cmd.get_one::<usize>("keysize").expect("required");
-
-                    let key = cmd.get_one::<PathBuf>("key").expect("required");
-                    let key_data = fs::read(key).unwrap();
-
-                    let algorithm_str = cmd.get_one::<String>("algorithm").expect("required");
-                    let algorithm = SignatureAlgorithm::from_str(algorithm_str.as_str()).unwrap();
-
-                    println!(
-                        "Verifying signed data for {} using key {} with algorithm {}",
-                        input.display(), key.display(), algorithm
-                    );
-
-                    // Perform the verification
-                    let message = match algorithm {
-                        SignatureAlgorithm::Falcon => {
-                            match key_size {
-                                1024 => Verify!(Falcon, key_data.to_owned(), 1024, input_data.to_owned(), Message),
-                                512 => Verify!(Falcon, key_data.to_owned(), 512, input_data.to_owned(), Message),
-                                _ => unreachable!(),
-                            }
-                        },
-                        SignatureAlgorithm::Dilithium => {
-                            match key_size {
-                                5 => Verify!(Dilithium, key_data.to_owned(), 5, input_data.to_owned(), Message),
-                                3 => Verify!(Dilithium, key_data.to_owned(), 3, input_data.to_owned(), Message),
-                                2 => Verify!(Dilithium, key_data.to_owned(), 2, input_data.to_owned(), Message),
-                                _ => unreachable!(),
-                            }
-                        },
-                        _ => unreachable!(),
-                    };
-
-                    // Create the parent directory if it does not exist
-                    create_parent_dir(&output).expect("Failed to create parent directory");
-
-                    fs::write(output, message).unwrap();
-                    println!("Verifying {} to {} using {} with algorithm {}", input.display(), output.display(), key.display(), algorithm);
-
-                    Ok(())
-                },
-                _ => unreachable!(),
-            }
-        },
-        _ => unreachable!(),
+    let prefix = stream::nonce_prefix(&header.algorithm)?;
+    header.nonce = Some(hex::encode(&prefix));
+    let header_bytes = header.to_bytes()?;
+    let stream_key = stream::derive_key(secret, passphrase);
+
+    writer.write_all(&header_bytes)?;
+    stream::encrypt(&header.algorithm, &stream_key, &prefix, &header_bytes, header.chunk_size as usize, reader, writer)
+}
+
+/// Generates a random data key and wraps it once for every recipient public key.
+fn wrap_for_recipients(recipients: &[&PathBuf]) -> Result<([u8; 32], Vec<Stanza>), CryptGuardError> {
+    if recipients.len() > u8::MAX as usize {
+        return Err(CryptGuardError::ParseError(format!("At most {} recipients are supported", u8::MAX)));
+    }
+
+    let data_key = recipient::generate_key();
+    let stanzas = recipients.iter()
+        .map(|path| {
+            let public_key = read_kyber_key(path, KeyRole::Public, "encryption")?;
+            eprintln!("Adding recipient {}", public_key.describe(path));
+            Stanza::wrap(&data_key, public_key.key_type.key_size(), public_key.key_type.kem().expect("checked by read_kyber_key"), &public_key.key)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((data_key, stanzas))
+}
+
+/// Recovers the secret the chunk key is derived from: the data key of a multi-recipient
+/// container, or the shared secret of its single KEM ciphertext.
+fn open_stream_secret(header: &Header, secret_key: &KeyFile, key_path: &Path) -> Result<Vec<u8>, CryptGuardError> {
+    if !header.has_recipients() {
+        return kem::decapsulate_with(header.kem, header.key_size, &secret_key.key, &header.cipher)
+            .map_err(|e| CryptGuardError::AuthError(format!("Key decapsulation failed: {}", e)));
+    }
+
+    let key_size = secret_key.key_type.key_size();
+    let kem = secret_key.key_type.kem();
+    header.recipients.iter()
+        .filter(|stanza| stanza.key_size == key_size && Some(stanza.kem) == kem)
+        .find_map(|stanza| stanza.unwrap(&secret_key.key))
+        .map(|data_key| data_key.to_vec())
+        .ok_or_else(|| CryptGuardError::AuthError(format!("{} is not one of the {} recipients of this container", key_path.display(), header.recipients.len())))
+}
+
+/// Digest of a file that is encrypted in a second pass; stdin can't be read twice.
+fn digest_file(path: &Path) -> Result<Vec<u8>, CryptGuardError> {
+    if stdio::is_stdio(path) {
+        return Err(CryptGuardError::ParseError("--sign-with can't be combined with chunked encryption of stdin; pass a file instead".to_string()));
+    }
+    Ok(digest::sha3_512(&mut stdio::open_input(path)?)?)
+}
+
+/// Signs the plaintext digest for `encrypt --sign-with`. The digest is only computed if there is a signer.
+fn embedded_signature(signer: Option<&KeyFile>, digest: impl FnOnce() -> Result<Vec<u8>, CryptGuardError>) -> Result<Option<EmbeddedSignature>, CryptGuardError> {
+    let key = match signer {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let signature = sign_with_key(key, digest()?, &SignatureType::Detached)?;
+    Ok(Some(EmbeddedSignature { key_type: key.key_type, signature }))
+}
+
+/// Checks the embedded signature against the digest of the decrypted plaintext, if `decrypt --verify-with` was given.
+fn check_embedded_signature(header: &Header, verifier: Option<&KeyFile>, digest: Vec<u8>) -> Result<(), CryptGuardError> {
+    let (embedded, key) = match (&header.signature, verifier) {
+        (Some(embedded), Some(key)) => (embedded, key),
+        _ => return Ok(()),
+    };
+    if embedded.key_type != key.key_type {
+        return Err(CryptGuardError::SignatureError(format!("The container was signed with a {} key, but --verify-with is a {} key", embedded.key_type, key.key_type)));
+    }
+
+    if !verify_with_key(key, embedded.signature.clone(), digest)? {
+        return Err(CryptGuardError::SignatureError("Embedded signature does not match the decrypted data".to_string()));
+    }
+    Ok(())
+}
+
+/// Decrypts the chunked payload following `header` into `writer` and checks the embedded signature.
+fn decrypt_stream<R: Read, W: Write>(reader: &mut R, writer: W, header: &Header, shared_secret: &[u8], passphrase: &str, verifier: Option<&KeyFile>) -> Result<u64, CryptGuardError> {
+    let prefix = hex::decode(header.nonce.as_deref().unwrap_or_default())
+        .map_err(|e| CryptGuardError::ParseError(format!("Invalid nonce prefix: {}", e)))?;
+    let header_bytes = header.to_bytes()?;
+    let stream_key = stream::derive_key(shared_secret, passphrase);
+
+    let mut writer = digest::HashingWriter::new(writer);
+    let total = stream::decrypt(&header.algorithm, &stream_key, &prefix, &header_bytes, header.chunk_size as usize, reader, &mut writer)?;
+    check_embedded_signature(header, verifier, writer.finalize())?;
+    Ok(total)
+}
+
+/// Like `decrypt_stream`, but writes to `output_path` and removes a partially written file on failure.
+fn decrypt_stream_file<R: Read>(reader: &mut R, output_path: &Path, header: &Header, shared_secret: &[u8], passphrase: &str, verifier: Option<&KeyFile>) -> Result<u64, CryptGuardError> {
+    // Data written to stdout can't be taken back, so it is held until the embedded signature checks out
+    if stdio::is_stdio(output_path) && header.signature.is_some() && verifier.is_some() {
+        let mut decrypted = Vec::new();
+        let total = decrypt_stream(reader, &mut decrypted, header, shared_secret, passphrase, verifier)?;
+        stdio::write_output(output_path, &decrypted)?;
+        return Ok(total);
+    }
+
+    let result = decrypt_stream(reader, stdio::create_output(output_path)?, header, shared_secret, passphrase, verifier);
+    if result.is_err() && !stdio::is_stdio(output_path) {
+        let _ = fs::remove_file(output_path);
+    }
+    result
+}
+
+fn invalid_key_size(key_size: usize, algorithm: impl fmt::Display) -> CryptGuardError {
+    CryptGuardError::ParseError(format!("Invalid key size {} for {}", key_size, algorithm))
+}
+
+/// The encrypted payload, the KEM ciphertext and the nonce for algorithms that produce one.
+type Encrypted = (Vec<u8>, Vec<u8>, Option<String>);
+
+/// Encrypts `data` with the Kyber public key.
+fn encrypt_data(algorithm: &SymmetricAlgorithm, key_data: Vec<u8>, key_size: usize, data: Vec<u8>, passphrase: &str) -> Result<Encrypted, CryptGuardError> {
+    let (shared_secret, cipher) = kem::encapsulate(key_size, &key_data)?;
+    let (encrypted, nonce) = payload::encrypt(algorithm, &shared_secret, passphrase, &data)?;
+    Ok((encrypted, cipher, Some(hex::encode(nonce))))
+}
+
+/// Reverses `encrypt_data` using the Kyber secret key and the values stored in the container header.
+fn decrypt_data(algorithm: &SymmetricAlgorithm, key_data: Vec<u8>, key_size: usize, data: Vec<u8>, passphrase: &str, cipher: Vec<u8>, nonce: Option<String>) -> Result<Vec<u8>, CryptGuardError> {
+    let nonce = nonce.ok_or_else(|| CryptGuardError::ParseError(format!("Nonce is required for {}", algorithm)))?;
+    let nonce = hex::decode(nonce).map_err(|e| CryptGuardError::ParseError(format!("Invalid nonce: {}", e)))?;
+
+    let shared_secret = kem::decapsulate(key_size, &key_data, &cipher)?;
+    payload::decrypt(algorithm, &shared_secret, passphrase, &nonce, &data)
+}
+
+/// Produces either a detached signature or signed data over `data`.
+fn sign_data(algorithm: &SignatureAlgorithm, key_data: Vec<u8>, key_size: usize, data: Vec<u8>, r#type: &SignatureType) -> Result<Vec<u8>, CryptGuardError> {
+    use ::crypt_guard::KDF::*;
+    let signature = match (r#type, algorithm, key_size) {
+        (SignatureType::SignedData, SignatureAlgorithm::Falcon, 1024) => Signature!(Falcon, key_data.clone(), 1024, data.clone(), Message),
+        (SignatureType::SignedData, SignatureAlgorithm::Falcon, 512) => Signature!(Falcon, key_data.clone(), 512, data.clone(), Message),
+        (SignatureType::SignedData, SignatureAlgorithm::Dilithium, 5) => Signature!(Dilithium, key_data.clone(), 5, data.clone(), Message),
+        (SignatureType::SignedData, SignatureAlgorithm::Dilithium, 3) => Signature!(Dilithium, key_data.clone(), 3, data.clone(), Message),
+        (SignatureType::SignedData, SignatureAlgorithm::Dilithium, 2) => Signature!(Dilithium, key_data.clone(), 2, data.clone(), Message),
+        (SignatureType::Detached, SignatureAlgorithm::Falcon, 1024) => Signature!(Falcon, key_data.clone(), 1024, data.clone(), Detached),
+        (SignatureType::Detached, SignatureAlgorithm::Falcon, 512) => Signature!(Falcon, key_data.clone(), 512, data.clone(), Detached),
+        (SignatureType::Detached, SignatureAlgorithm::Dilithium, 5) => Signature!(Dilithium, key_data.clone(), 5, data.clone(), Detached),
+        (SignatureType::Detached, SignatureAlgorithm::Dilithium, 3) => Signature!(Dilithium, key_data.clone(), 3, data.clone(), Detached),
+        (SignatureType::Detached, SignatureAlgorithm::Dilithium, 2) => Signature!(Dilithium, key_data.clone(), 2, data.clone(), Detached),
+        (SignatureType::SignedData, SignatureAlgorithm::MlDsa, 87 | 65 | 44) => ml_dsa::sign_message(key_size, &key_data, &data)?,
+        (SignatureType::Detached, SignatureAlgorithm::MlDsa, 87 | 65 | 44) => ml_dsa::sign(key_size, &key_data, &data)?,
+        _ => return Err(invalid_key_size(key_size, algorithm)),
+    };
+    Ok(signature)
+}
+
+/// Checks a detached signature over `data`.
+fn verify_detached_data(algorithm: &SignatureAlgorithm, key_data: Vec<u8>, key_size: usize, signature: Vec<u8>, data: Vec<u8>) -> Result<bool, CryptGuardError> {
+    use pqcrypto_dilithium::{dilithium2, dilithium3, dilithium5};
+    use pqcrypto_falcon::{falcon1024, falcon512};
+    // Falcon and Dilithium go to pqcrypto directly, Verify! panics on a signature that doesn't verify
+    let is_valid = match (algorithm, key_size) {
+        (SignatureAlgorithm::Falcon, 1024) => verify_pqclean(falcon1024::verify_detached_signature, &key_data, &signature, &data)?,
+        (SignatureAlgorithm::Falcon, 512) => verify_pqclean(falcon512::verify_detached_signature, &key_data, &signature, &data)?,
+        (SignatureAlgorithm::Dilithium, 5) => verify_pqclean(dilithium5::verify_detached_signature, &key_data, &signature, &data)?,
+        (SignatureAlgorithm::Dilithium, 3) => verify_pqclean(dilithium3::verify_detached_signature, &key_data, &signature, &data)?,
+        (SignatureAlgorithm::Dilithium, 2) => verify_pqclean(dilithium2::verify_detached_signature, &key_data, &signature, &data)?,
+        (SignatureAlgorithm::MlDsa, 87 | 65 | 44) => ml_dsa::verify(key_size, &key_data, &signature, &data)?,
+        _ => return Err(invalid_key_size(key_size, algorithm)),
+    };
+    Ok(is_valid)
+}
+
+/// Verifies signed data and returns the embedded message.
+fn open_signed_data(algorithm: &SignatureAlgorithm, key_data: Vec<u8>, key_size: usize, signed: Vec<u8>) -> Result<Vec<u8>, CryptGuardError> {
+    use pqcrypto_dilithium::{dilithium2, dilithium3, dilithium5};
+    use pqcrypto_falcon::{falcon1024, falcon512};
+    let message = match (algorithm, key_size) {
+        (SignatureAlgorithm::Falcon, 1024) => open_pqclean(falcon1024::open, &key_data, &signed)?,
+        (SignatureAlgorithm::Falcon, 512) => open_pqclean(falcon512::open, &key_data, &signed)?,
+        (SignatureAlgorithm::Dilithium, 5) => open_pqclean(dilithium5::open, &key_data, &signed)?,
+        (SignatureAlgorithm::Dilithium, 3) => open_pqclean(dilithium3::open, &key_data, &signed)?,
+        (SignatureAlgorithm::Dilithium, 2) => open_pqclean(dilithium2::open, &key_data, &signed)?,
+        (SignatureAlgorithm::MlDsa, 87 | 65 | 44) => ml_dsa::open_message(key_size, &key_data, &signed)
+            .map_err(|e| CryptGuardError::SignatureError(e.to_string()))?,
+        _ => return Err(invalid_key_size(key_size, algorithm)),
+    };
+    Ok(message)
+}
+
+/// Checks a detached Falcon or Dilithium signature; a malformed signature is simply invalid.
+fn verify_pqclean<K: pqcrypto_traits::sign::PublicKey, S: pqcrypto_traits::sign::DetachedSignature, E>(
+    verify: fn(&S, &[u8], &K) -> Result<(), E>,
+    public_key: &[u8],
+    signature: &[u8],
+    data: &[u8],
+) -> Result<bool, CryptGuardError> {
+    let public_key = K::from_bytes(public_key).map_err(|e| CryptGuardError::ParseError(format!("Invalid public key: {}", e)))?;
+    match S::from_bytes(signature) {
+        Ok(signature) => Ok(verify(&signature, data, &public_key).is_ok()),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Opens Falcon or Dilithium signed data, returning the message if the signature verifies.
+fn open_pqclean<K: pqcrypto_traits::sign::PublicKey, M: pqcrypto_traits::sign::SignedMessage, E>(
+    open: fn(&M, &K) -> Result<Vec<u8>, E>,
+    public_key: &[u8],
+    signed: &[u8],
+) -> Result<Vec<u8>, CryptGuardError> {
+    let public_key = K::from_bytes(public_key).map_err(|e| CryptGuardError::ParseError(format!("Invalid public key: {}", e)))?;
+    let signed = M::from_bytes(signed).map_err(|e| CryptGuardError::SignatureError(format!("Malformed signed data: {}", e)))?;
+    open(&signed, &public_key).map_err(|_| CryptGuardError::SignatureError("Signature verification failed".to_string()))
+}
+
+/// Signs `data` with a Falcon, Dilithium, ML-DSA, SLH-DSA or composite secret key. A composite
+/// signature is the Ed25519 signature followed by the post-quantum one, both over the same message.
+fn sign_with_key(key: &KeyFile, data: Vec<u8>, r#type: &SignatureType) -> Result<Vec<u8>, CryptGuardError> {
+    if let Some(parameters) = key.key_type.slh_dsa_parameters() {
+        return Ok(match r#type {
+            SignatureType::Detached => slh_dsa::sign(parameters, &key.key, &data)?,
+            SignatureType::SignedData => slh_dsa::sign_message(parameters, &key.key, &data)?,
+        });
+    }
+
+    let algorithm = key.key_type.signature_algorithm().expect("checked by read_signature_key");
+    if !key.key_type.is_composite() {
+        return sign_data(&algorithm, key.key.clone(), key.key_type.key_size(), data, r#type);
+    }
+
+    let (seed, pq_secret) = composite::split_key(&key.key)?;
+    let message = composite::message(&data);
+    let ed25519 = composite::sign_ed25519(&seed, &message);
+    let pq = sign_data(&algorithm, pq_secret.to_vec(), key.key_type.key_size(), message, r#type)?;
+    Ok([ed25519.as_slice(), &pq].concat())
+}
+
+/// Checks a detached signature made by `sign_with_key`; composite signatures need both halves to verify.
+fn verify_with_key(key: &KeyFile, signature: Vec<u8>, data: Vec<u8>) -> Result<bool, CryptGuardError> {
+    if let Some(parameters) = key.key_type.slh_dsa_parameters() {
+        return Ok(slh_dsa::verify(parameters, &key.key, &signature, &data)?);
+    }
+
+    let algorithm = key.key_type.signature_algorithm().expect("checked by read_signature_key");
+    if !key.key_type.is_composite() {
+        return verify_detached_data(&algorithm, key.key.clone(), key.key_type.key_size(), signature, data);
+    }
+
+    let (ed25519_public, pq_public) = composite::split_key(&key.key)?;
+    let (ed25519, pq) = composite::split_signature(&signature)?;
+    let message = composite::message(&data);
+    let ed25519_valid = composite::verify_ed25519(&ed25519_public, &message, &ed25519);
+    let pq_valid = verify_detached_data(&algorithm, pq_public.to_vec(), key.key_type.key_size(), pq.to_vec(), message)?;
+    Ok(ed25519_valid && pq_valid)
+}
+
+/// Verifies signed data made by `sign_with_key` and returns the embedded message.
+fn open_with_key(key: &KeyFile, signed: Vec<u8>) -> Result<Vec<u8>, CryptGuardError> {
+    if let Some(parameters) = key.key_type.slh_dsa_parameters() {
+        return slh_dsa::open_message(parameters, &key.key, &signed)
+            .map_err(|e| CryptGuardError::SignatureError(e.to_string()));
+    }
+
+    let algorithm = key.key_type.signature_algorithm().expect("checked by read_signature_key");
+    if !key.key_type.is_composite() {
+        return open_signed_data(&algorithm, key.key.clone(), key.key_type.key_size(), signed);
+    }
+
+    let (ed25519_public, pq_public) = composite::split_key(&key.key)?;
+    let (ed25519, pq) = composite::split_signature(&signed)?;
+    let message = open_signed_data(&algorithm, pq_public.to_vec(), key.key_type.key_size(), pq.to_vec())?;
+    if !composite::verify_ed25519(&ed25519_public, &message, &ed25519) {
+        return Err(CryptGuardError::SignatureError("The Ed25519 signature of the composite signed data is invalid".to_string()));
+    }
+    Ok(composite::strip_message(&message)?.to_vec())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, CryptGuardError> {
+    fs::read(path).map_err(|e| CryptGuardError::IoError(std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))))
+}
+
+/// Reads a Kyber, hybrid X25519+Kyber or ML-KEM key and makes sure it has the expected role.
+fn read_kyber_key(path: &Path, role: KeyRole, purpose: &str) -> Result<KeyFile, CryptGuardError> {
+    let key = KeyFile::read(&keyring::resolve(path, role)?)?;
+    key.require(role, purpose, |key_type| key_type.kem().is_some())?;
+    Ok(key)
+}
+
+/// Reads a Falcon or Dilithium key, checking it against the optional `--algorithm` argument.
+fn read_signature_key(path: &Path, role: KeyRole, purpose: &str, expected: Option<&String>) -> Result<(KeyFile, SignatureAlgorithm), CryptGuardError> {
+    let key = KeyFile::read(&keyring::resolve(path, role)?)?;
+    key.require(role, purpose, |key_type| key_type.signature_algorithm().is_some())?;
+
+    let algorithm = key.key_type.signature_algorithm().expect("checked by require");
+    if let Some(expected) = expected {
+        let expected = SignatureAlgorithm::from_str(expected)?;
+        if expected != algorithm {
+            return Err(CryptGuardError::ParseError(format!("--algorithm {} does not match the {} key {}", expected, key.key_type, path.display())));
+        }
+    }
+    Ok((key, algorithm))
+}
+
+/// Decrypts a passphrase-protected secret key in memory, reading the passphrase as the `--key-passphrase-*` options say.
+fn unlock_secret_key(key: &mut KeyFile, path: &Path, matches: &ArgMatches) -> Result<(), CryptGuardError> {
+    if key.is_sealed() {
+        let passphrase = passphrase::read(&Source::for_key(matches), &format!("Passphrase for {}", path.display()), false)?;
+        key.unseal(&passphrase)?;
+    }
+    Ok(())
+}
+
+fn keygen(sub_matches: &ArgMatches) -> Result<(), CryptGuardError> {
+    use ::crypt_guard::KDF::*;
+    let algorithm = sub_matches.get_one::<String>("algorithm").expect("required");
+    let keytype = KeyTypes::from_str(algorithm.as_str())?;
+
+    let keyring = match sub_matches.get_one::<String>("name") {
+        Some(name) => {
+            let keyring = Keyring::open()?;
+            if keyring.get(name).is_some() {
+                return Err(CryptGuardError::ParseError(format!("The keyring already holds a key named {}", name)));
+            }
+            eprintln!("Generating key pair with algorithm {} as {} in the keyring at {}", keytype, name, keyring.root().display());
+            Some((keyring, name))
+        },
+        None => {
+            let directory = sub_matches.get_one::<PathBuf>("directory").expect("required without --name");
+            eprintln!("Generating key pair with algorithm {} in directory {:?}", keytype, directory);
+            None
+        },
+    };
+
+    let (public, secret) = match keytype {
+        KeyTypes::Falcon1024 => FalconKeypair!(1024),
+        KeyTypes::Falcon512 => FalconKeypair!(512),
+        KeyTypes::Kyber1024 => KyberKeypair!(1024),
+        KeyTypes::Kyber768 => KyberKeypair!(768),
+        KeyTypes::Kyber512 => KyberKeypair!(512),
+        KeyTypes::Dilithium5 => DilithiumKeypair!(5),
+        KeyTypes::Dilithium3 => DilithiumKeypair!(3),
+        KeyTypes::Dilithium2 => DilithiumKeypair!(2),
+        KeyTypes::X25519Kyber1024 => {
+            let (public, secret) = KyberKeypair!(1024);
+            kem::hybrid_keypair(public, secret)
+        },
+        KeyTypes::X25519Kyber768 => {
+            let (public, secret) = KyberKeypair!(768);
+            kem::hybrid_keypair(public, secret)
+        },
+        KeyTypes::X25519Kyber512 => {
+            let (public, secret) = KyberKeypair!(512);
+            kem::hybrid_keypair(public, secret)
+        },
+        KeyTypes::Ed25519Falcon1024 => {
+            let (public, secret) = FalconKeypair!(1024);
+            composite::keypair(public, secret)
+        },
+        KeyTypes::Ed25519Falcon512 => {
+            let (public, secret) = FalconKeypair!(512);
+            composite::keypair(public, secret)
+        },
+        KeyTypes::Ed25519Dilithium5 => {
+            let (public, secret) = DilithiumKeypair!(5);
+            composite::keypair(public, secret)
+        },
+        KeyTypes::Ed25519Dilithium3 => {
+            let (public, secret) = DilithiumKeypair!(3);
+            composite::keypair(public, secret)
+        },
+        KeyTypes::Ed25519Dilithium2 => {
+            let (public, secret) = DilithiumKeypair!(2);
+            composite::keypair(public, secret)
+        },
+        KeyTypes::MlKem1024 | KeyTypes::MlKem768 | KeyTypes::MlKem512 => kem::ml_kem_keypair(keytype.key_size())?,
+        KeyTypes::MlDsa87 | KeyTypes::MlDsa65 | KeyTypes::MlDsa44 => ml_dsa::keypair(keytype.key_size())?,
+        KeyTypes::SlhDsaSha2_128s | KeyTypes::SlhDsaSha2_128f | KeyTypes::SlhDsaSha2_192s
+            | KeyTypes::SlhDsaSha2_192f | KeyTypes::SlhDsaSha2_256s | KeyTypes::SlhDsaSha2_256f =>
+            slh_dsa::keypair(keytype.slh_dsa_parameters().expect("SLH-DSA key type"))?,
+    };
+
+    let (public_key_path, secret_key_path) = match keyring {
+        Some((keyring, name)) => {
+            let identity = keyring.create(name, sub_matches.get_one::<String>("label"))?;
+            (identity.key_path(KeyRole::Public), identity.key_path(KeyRole::Secret))
+        },
+        None => {
+            let directory = sub_matches.get_one::<PathBuf>("directory").expect("required without --name");
+            let dir_name = directory.file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| CryptGuardError::ParseError(format!("Invalid directory name: {}", directory.display())))?;
+
+            // Create the directory if it does not exist
+            fs::create_dir_all(directory)?;
+            (directory.join(format!("{}.pub", dir_name)), directory.join(format!("{}.sec", dir_name)))
+        },
+    };
+
+    // Save the public key
+    let armored = sub_matches.get_flag("armor");
+    KeyFile::new(keytype, KeyRole::Public, public.clone()).write(&public_key_path, armored)?;
+
+    // Save the secret key, sealed with a passphrase if requested
+    let mut secret_key = KeyFile::new(keytype, KeyRole::Secret, secret).with_public_key(public);
+    if sub_matches.get_flag("protect") {
+        let passphrase = passphrase::read(&Source::for_key(sub_matches), "Passphrase for the secret key", true)?;
+        secret_key.seal(&passphrase)?;
+    }
+    secret_key.write(&secret_key_path, armored)?;
+
+    eprintln!("Keys generated and saved to {} and {}", public_key_path.display(), secret_key_path.display());
+    Ok(())
+}
+
+fn encrypt(sub_matches: &ArgMatches) -> Result<(), CryptGuardError> {
+    let input = sub_matches.get_one::<String>("input").expect("required");
+    let output = sub_matches.get_one::<String>("output");
+    let output_path = PathBuf::from(output.map_or(stdio::STDIO, String::as_str));
+
+    // An inline message encrypted to the terminal comes back as text that decrypt --message accepts
+    let encoding = match sub_matches.get_one::<String>("encoding") {
+        Some(encoding) => Some(Encoding::from_str(encoding)?),
+        None if output.is_none() => Some(Encoding::Base64),
+        None => None,
+    };
+
+    let source = Source::for_data(sub_matches)
+        .or_else(|| sub_matches.get_flag("ask-passphrase").then_some(Source::Prompt));
+    let algorithm_str = sub_matches.get_one::<String>("algorithm").expect("required");
+    let algorithm = SymmetricAlgorithm::from_str(algorithm_str)?;
+
+    // Without a passphrase the data is protected by the Kyber key alone
+    let (kdf, derived) = match source {
+        Some(source) => {
+            let passphrase = passphrase::read(&source, "Passphrase", true)?;
+            let kdf = Kdf::argon2id();
+            let derived = kdf.derive(&passphrase)?;
+            (kdf, derived)
+        },
+        None => (Kdf::None, String::new()),
+    };
+
+    let signer = match sub_matches.get_one::<PathBuf>("sign-with") {
+        Some(path) => {
+            let (mut signing_key, _) = read_signature_key(path, KeyRole::Secret, "--sign-with", None)?;
+            unlock_secret_key(&mut signing_key, path, sub_matches)?;
+            Some(signing_key)
+        },
+        None => None,
+    };
+
+    let message = sub_matches.get_flag("message");
+    let input_path = PathBuf::from(input);
+
+    // Directories are packed into a tar archive and then encrypted like any other in-memory input
+    let archive = match !message && input_path.is_dir() {
+        true => Some(archive::pack(&input_path)?),
+        false => None,
+    };
+    let in_memory: Option<&[u8]> = match message {
+        true => Some(input.as_bytes()),
+        false => archive.as_deref(),
+    };
+    let input_name = match message {
+        true => input.clone(),
+        false => stdio::display(&input_path, "<stdin>"),
+    };
+
+    let mut header = Header { algorithm, key_size: 0, kdf, cipher: Vec::new(), nonce: None, chunk_size: 0, recipients: Vec::new(), signature: None, archive: archive.is_some(), kem: Kem::Kyber };
+
+    let public_key = match sub_matches.get_one::<PathBuf>("key") {
+        Some(key) => Some((key, read_kyber_key(key, KeyRole::Public, "encryption")?)),
+        None => None,
+    };
+    // Hybrid and ML-KEM secrets can only key the chunked format, Encryption! knows plain Kyber alone
+    let kem = public_key.as_ref().and_then(|(_, public_key)| public_key.key_type.kem()).unwrap_or(Kem::Kyber);
+
+    if sub_matches.get_flag("stream") || sub_matches.contains_id("recipient") || kem != Kem::Kyber {
+        header.chunk_size = *sub_matches.get_one::<u32>("chunk-size").expect("default");
+        header.signature = embedded_signature(signer.as_ref(), || match in_memory {
+            Some(mut data) => Ok(digest::sha3_512(&mut data)?),
+            None => digest_file(&input_path),
+        })?;
+
+        let (secret, encrypted_for) = match sub_matches.get_many::<PathBuf>("recipient") {
+            Some(recipients) => {
+                let recipients: Vec<&PathBuf> = recipients.collect();
+                let (data_key, stanzas) = wrap_for_recipients(&recipients)?;
+                header.recipients = stanzas;
+                (data_key.to_vec(), format!("{} recipients", recipients.len()))
+            },
+            None => {
+                let (key, public_key) = public_key.expect("required without --recipient");
+                header.key_size = public_key.key_type.key_size();
+                header.kem = kem;
+                let (shared_secret, cipher) = kem::encapsulate_with(kem, header.key_size, &public_key.key)?;
+                header.cipher = cipher;
+                (shared_secret, public_key.describe(key))
+            },
+        };
+
+        let chunk_size = header.chunk_size;
+        let mut reader: Box<dyn Read + '_> = match in_memory {
+            Some(data) => Box::new(data),
+            None => stdio::open_input(&input_path)?,
+        };
+        let mut output = stdio::create_output(&output_path)?;
+        let total = match (sub_matches.get_flag("armor"), encoding) {
+            (true, _) => {
+                let mut writer = ArmorWriter::new(&mut output, armor::ENCRYPTED_MESSAGE)?;
+                let total = encrypt_stream(&mut reader, &mut writer, header, &secret, &derived)?;
+                writer.finish()?;
+                total
+            },
+            (false, Some(encoding)) => {
+                let mut container = Vec::new();
+                let total = encrypt_stream(&mut reader, &mut container, header, &secret, &derived)?;
+                writeln!(output, "{}", encoding.encode(&container))?;
+                total
+            },
+            (false, None) => encrypt_stream(&mut reader, &mut output, header, &secret, &derived)?,
+        };
+        output.flush()?;
+
+        eprintln!("Encrypting {} to {} using {} in chunks of {} bytes has finished, {} bytes processed", input_name, stdio::display(&output_path, "<stdout>"), encrypted_for, chunk_size, total);
+        return Ok(());
+    }
+
+    let (key, public_key) = public_key.expect("required without --recipient");
+    let key_name = public_key.describe(key);
+    let key_size = public_key.key_type.key_size();
+    let key_data = public_key.key;
+    header.key_size = key_size;
+
+    let input_data = match in_memory {
+        Some(data) => data.to_vec(),
+        None => stdio::read_input(&input_path)?,
+    };
+    header.signature = embedded_signature(signer.as_ref(), || Ok(digest::sha3_512(&mut input_data.as_slice())?))?;
+
+    eprintln!("Encrypting {} to {} using {} with algorithm {}", input_name, stdio::display(&output_path, "<stdout>"), key_name, header.algorithm);
+    let (encrypted, cipher, nonce) = encrypt_data(&header.algorithm, key_data, key_size, input_data, derived.as_str())?;
+    header.cipher = cipher;
+    header.nonce = nonce;
+    write_container_file(&output_path, &header, &encrypted, sub_matches.get_flag("armor"), encoding)?;
+
+    eprintln!("Encrypting {} to {} using {} with algorithm {} has finished", input_name, stdio::display(&output_path, "<stdout>"), key_name, header.algorithm);
+    Ok(())
+}
+
+fn decrypt(sub_matches: &ArgMatches) -> Result<(), CryptGuardError> {
+    let input = sub_matches.get_one::<String>("input").expect("required");
+    let output = sub_matches.get_one::<String>("output").map_or(stdio::STDIO, String::as_str);
+
+    let key = sub_matches.get_one::<PathBuf>("key").expect("required");
+
+    let message = sub_matches.get_flag("message");
+    let input_path = PathBuf::from(input);
+    let output_path = PathBuf::from(output);
+    let input_name = match message {
+        true => "<message>".to_string(),
+        false => stdio::display(&input_path, "<stdin>"),
+    };
+
+    let mut input_file: Box<dyn Read> = match message {
+        true => Box::new(std::io::Cursor::new(armor::decode_inline(input, container::MAGIC)?)),
+        false => armor::reader(stdio::open_input(&input_path)?)?,
+    };
+    let header = Header::read_from(&mut input_file)?;
+
+    eprintln!("Decrypting {} to {} using {} with algorithm {}", input_name, stdio::display(&output_path, "<stdout>"), key.display(), header.algorithm);
+    if header.archive && stdio::is_stdio(&output_path) {
+        return Err(CryptGuardError::ParseError("The container holds a directory archive; pass a directory to -o instead of -".to_string()));
+    }
+
+    let mut secret_key = read_kyber_key(key, KeyRole::Secret, "decryption")?;
+    if !header.has_recipients() && (secret_key.key_type.key_size() != header.key_size || secret_key.key_type.kem() != Some(header.kem)) {
+        return Err(CryptGuardError::ParseError(format!("The container was encrypted for {}, but {} is a {} key", header.kem.name(header.key_size), key.display(), secret_key.key_type)));
+    }
+    unlock_secret_key(&mut secret_key, key, sub_matches)?;
+
+    let verifier = match sub_matches.get_one::<PathBuf>("verify-with") {
+        Some(path) => Some(read_signature_key(path, KeyRole::Public, "--verify-with", None)?.0),
+        None => None,
+    };
+    match (&header.signature, &verifier) {
+        (None, Some(_)) => return Err(CryptGuardError::SignatureError("The container carries no signature, but --verify-with was given".to_string())),
+        (Some(embedded), None) => eprintln!("The container is signed with a {} key; pass --verify-with to check the signature", embedded.key_type),
+        _ => {},
+    }
+
+    let derived = match header.kdf {
+        Kdf::None => {
+            if Source::for_data(sub_matches).is_some() {
+                eprintln!("The container is not protected by a passphrase, ignoring the given one");
+            }
+            String::new()
+        },
+        _ => {
+            let source = Source::for_data(sub_matches).unwrap_or(Source::Prompt);
+            header.kdf.derive(&passphrase::read(&source, "Passphrase", false)?)?
+        },
+    };
+
+    let decrypted = match header.is_chunked() {
+        true => {
+            let shared_secret = open_stream_secret(&header, &secret_key, key)?;
+            if !header.archive {
+                decrypt_stream_file(&mut input_file, &output_path, &header, &shared_secret, &derived, verifier.as_ref())?;
+                eprintln!("Finished decryption of: {}", input_name);
+                return Ok(());
+            }
+
+            let mut decrypted = Vec::new();
+            decrypt_stream(&mut input_file, &mut decrypted, &header, &shared_secret, &derived, verifier.as_ref())?;
+            decrypted
+        },
+        false => {
+            let mut payload = Vec::new();
+            input_file.read_to_end(&mut payload)?;
+            let decrypted = decrypt_data(&header.algorithm, secret_key.key, header.key_size, payload, derived.as_str(), header.cipher.clone(), header.nonce.clone())?;
+            check_embedded_signature(&header, verifier.as_ref(), digest::sha3_512(&mut decrypted.as_slice())?)?;
+            decrypted
+        },
+    };
+
+    match header.archive {
+        true => {
+            let entries = archive::unpack(&decrypted, &output_path)?;
+            eprintln!("Restored {} archive entries into {}", entries, output_path.display());
+        },
+        false => stdio::write_output(&output_path, &decrypted)?,
+    }
+
+    eprintln!("Finished decryption of: {}", input_name);
+    Ok(())
+}
+
+/// The unsigned packet `sign` should wrap its signature in, if `--packet`, `--context` or `--expires` was given.
+fn signature_packet(sub_matches: &ArgMatches, secret_key: &KeyFile) -> Result<Option<Packet>, CryptGuardError> {
+    let context = sub_matches.get_one::<String>("context");
+    let expires = sub_matches.get_one::<String>("expires");
+    if !sub_matches.get_flag("packet") && context.is_none() && expires.is_none() {
+        return Ok(None);
+    }
+
+    let lifetime = expires.map(|expires| packet::parse_lifetime(expires)).transpose()?;
+    Ok(Some(Packet::new(secret_key, context.cloned().unwrap_or_default(), lifetime)?))
+}
+
+/// Fills in the signature of `packet` over data with the given digest.
+fn sign_packet(key: &KeyFile, mut packet: Packet, digest: &[u8]) -> Result<Packet, CryptGuardError> {
+    packet.signature = sign_with_key(key, packet.message(digest), &SignatureType::Detached)?;
+    Ok(packet)
+}
+
+/// Signs `data` as a bare signature, or inside `packet` if there is one; signed data is then the packet followed by `data`.
+fn sign_for_output(key: &KeyFile, packet: Option<Packet>, data: Vec<u8>, r#type: &SignatureType) -> Result<Vec<u8>, CryptGuardError> {
+    let packet = match packet {
+        Some(packet) => packet,
+        None => return sign_with_key(key, data, r#type),
+    };
+
+    let digest = packet.hash.digest(&mut data.as_slice())?;
+    let packet = sign_packet(key, packet, &digest)?;
+    Ok(match r#type {
+        SignatureType::Detached => packet.to_bytes(),
+        SignatureType::SignedData => [packet.to_bytes(), data].concat(),
+    })
+}
+
+/// Verifies a signature packet over the data `reader` yields, streaming it through the packet's hash.
+fn verify_packet<R: Read>(key: &KeyFile, packet: &Packet, reader: &mut R, context: Option<&String>) -> Result<(), CryptGuardError> {
+    verify_packet_digest(key, packet, &packet.hash.digest(reader)?, context)
+}
+
+/// Verifies a signature packet over data with the given digest, made by `key` and, if `context` is given, for that
+/// context. Expiry is left to the caller, so the now authentic fields can be shown before an expired signature is rejected.
+fn verify_packet_digest(key: &KeyFile, packet: &Packet, digest: &[u8], context: Option<&String>) -> Result<(), CryptGuardError> {
+    packet.check_signer(key)?;
+    if let Some(context) = context {
+        if packet.context != *context {
+            return Err(CryptGuardError::SignatureError(format!("The signature was made for context \"{}\", not \"{}\"", packet.context, context)));
+        }
+    }
+
+    if !verify_with_key(key, packet.signature.clone(), packet.message(digest))? {
+        return Err(CryptGuardError::SignatureError("The signature packet is invalid".to_string()));
+    }
+    Ok(())
+}
+
+/// Writes `packet`, followed for signed data by the contents of `message`, copied in a second pass.
+fn write_packet<W: Write>(mut writer: W, packet: &Packet, message: Option<&Path>) -> Result<W, CryptGuardError> {
+    writer.write_all(&packet.to_bytes())?;
+    if let Some(message) = message {
+        std::io::copy(&mut stdio::open_input(message)?, &mut writer)?;
+    }
+    Ok(writer)
+}
+
+fn sign(sub_matches: &ArgMatches) -> Result<(), CryptGuardError> {
+    let output = sub_matches.get_one::<String>("output").expect("required");
+    let output_path = PathBuf::from(output);
+
+    let key = sub_matches.get_one::<PathBuf>("key").expect("required");
+    let (mut secret_key, algorithm) = read_signature_key(key, KeyRole::Secret, "signing", sub_matches.get_one::<String>("algorithm"))?;
+    unlock_secret_key(&mut secret_key, key, sub_matches)?;
+    let packet = signature_packet(sub_matches, &secret_key)?;
+
+    if let Some(dir) = sub_matches.get_one::<PathBuf>("manifest") {
+        return sign_manifest(sub_matches, dir, &output_path, &secret_key, key, &algorithm, packet);
+    }
+
+    let input = sub_matches.get_one::<String>("input").expect("required without --manifest");
+    if sub_matches.get_flag("clearsign") {
+        return clearsign(sub_matches, input, &output_path, &secret_key, key, &algorithm, packet);
+    }
+
+    let type_str = sub_matches.get_one::<String>("type").expect("required without --manifest");
+    let r#type = SignatureType::from_str(type_str)?;
+
+    let key_name = secret_key.describe(key);
+    let input_path = Path::new(input);
+    let is_message = sub_matches.get_flag("message");
+
+    // A packet signs a digest, so files are streamed through the hash; signed data from stdin
+    // can't be read a second time for the copy and falls back to memory
+    match packet {
+        Some(packet) if !is_message && (r#type == SignatureType::Detached || !stdio::is_stdio(input_path)) => {
+            let digest = packet.hash.digest(&mut stdio::open_input(input_path)?)?;
+            let packet = sign_packet(&secret_key, packet, &digest)?;
+            let message = match r#type {
+                SignatureType::Detached => None,
+                SignatureType::SignedData => Some(input_path),
+            };
+            if message.is_some() && fs::canonicalize(input_path).ok() == fs::canonicalize(&output_path).ok() {
+                return Err(CryptGuardError::ParseError("Signed data is copied from the input after hashing it, so --output must not be the input file".to_string()));
+            }
+
+            let mut output = stdio::create_output(&output_path)?;
+            match (sub_matches.get_flag("armor"), &r#type) {
+                (true, SignatureType::Detached) => write_packet(ArmorWriter::new(&mut output, armor::SIGNATURE)?, &packet, message)?.finish()?,
+                (true, SignatureType::SignedData) => write_packet(ArmorWriter::new(&mut output, armor::SIGNED_MESSAGE)?, &packet, message)?.finish()?,
+                (false, _) => write_packet(&mut output, &packet, message)?,
+            };
+            output.flush()?;
+
+            eprintln!("Signing {} to {} using {} with algorithm {}", input, stdio::display(&output_path, "<stdout>"), key_name, algorithm);
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    let input_data = match is_message {
+        true => input.as_bytes().to_owned(),
+        false => stdio::read_input(input_path)?,
+    };
+    let signature = sign_for_output(&secret_key, packet, input_data, &r#type)?;
+    let signature = match (sub_matches.get_flag("armor"), &r#type) {
+        (true, SignatureType::Detached) => armor::encode(armor::SIGNATURE, &signature),
+        (true, SignatureType::SignedData) => armor::encode(armor::SIGNED_MESSAGE, &signature),
+        (false, _) => signature,
+    };
+
+    stdio::write_output(&output_path, &signature)?;
+    eprintln!("Signing {} to {} using {} with algorithm {}", input, stdio::display(&output_path, "<stdout>"), key_name, algorithm);
+    Ok(())
+}
+
+/// `sign --clearsign`: the normalized text followed by an armored signature packet over its canonical form.
+fn clearsign(sub_matches: &ArgMatches, input: &str, output: &Path, secret_key: &KeyFile, key: &Path, algorithm: &SignatureAlgorithm, packet: Option<Packet>) -> Result<(), CryptGuardError> {
+    let text = match sub_matches.get_flag("message") {
+        true => input.to_string(),
+        false => String::from_utf8(stdio::read_input(Path::new(input))?)
+            .map_err(|_| CryptGuardError::ParseError(format!("{} is not UTF-8 text; use --type detached or signed for binary data", input)))?,
+    };
+
+    // Cleartext signatures always carry a packet, so verification can tell what was signed and by whom
+    let packet = match packet {
+        Some(packet) => packet,
+        None => Packet::new(secret_key, String::new(), None)?,
+    };
+    let normalized = cleartext::normalize(&text);
+    let digest = packet.hash.digest(&mut cleartext::canonicalize(&normalized).as_slice())?;
+    let packet = sign_packet(secret_key, packet, &digest)?;
+
+    stdio::write_output(output, &cleartext::encode(&normalized, &packet.to_bytes()))?;
+    eprintln!("Signing {} to {} as cleartext using {} with algorithm {}", input, stdio::display(output, "<stdout>"), secret_key.describe(key), algorithm);
+    Ok(())
+}
+
+/// SHA-256 of every file below `dir`, hashed in parallel, as `(digest, relative path)` pairs in path order.
+fn hash_directory(dir: &Path, exclude: &[PathBuf]) -> Result<Vec<(String, String)>, CryptGuardError> {
+    manifest::list_files(dir, exclude)?
+        .into_par_iter()
+        .map(|path| -> Result<(String, String), CryptGuardError> {
+            let digest = digest::sha256(&mut manifest::open_listed(dir, &path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)))?)?;
+            Ok((hex::encode(digest), path))
+        })
+        .collect()
+}
+
+/// `sign --manifest`: writes a SHA256SUMS manifest of `dir` to `output` and a detached signature of it to `<output>.sig`.
+fn sign_manifest(sub_matches: &ArgMatches, dir: &Path, output: &Path, secret_key: &KeyFile, key: &Path, algorithm: &SignatureAlgorithm, packet: Option<Packet>) -> Result<(), CryptGuardError> {
+    if stdio::is_stdio(output) {
+        return Err(CryptGuardError::ParseError("--manifest writes the manifest and its signature side by side; pass a file for --output".to_string()));
+    }
+    if !dir.is_dir() {
+        return Err(CryptGuardError::ParseError(format!("{} is not a directory", dir.display())));
+    }
+
+    // The manifest and its signature may live in the directory they describe; they must not list themselves
+    let signature_path = manifest::signature_path(output);
+    let checksums = hash_directory(dir, &[output.to_path_buf(), signature_path.clone()])?;
+    if checksums.is_empty() {
+        return Err(CryptGuardError::ParseError(format!("{} contains no files", dir.display())));
+    }
+    let text = manifest::format_checksums(&checksums);
+
+    let signature = sign_for_output(secret_key, packet, text.as_bytes().to_vec(), &SignatureType::Detached)?;
+    let signature = match sub_matches.get_flag("armor") {
+        true => armor::encode(armor::SIGNATURE, &signature),
+        false => signature,
+    };
+
+    stdio::write_output(output, text.as_bytes())?;
+    stdio::write_output(&signature_path, &signature)?;
+    eprintln!(
+        "Signed manifest of {} files in {} to {} and {} using {} with algorithm {}",
+        checksums.len(), dir.display(), output.display(), signature_path.display(), secret_key.describe(key), algorithm
+    );
+    Ok(())
+}
+
+fn verify_detached(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let input = cmd.get_one::<String>("input").expect("required");
+    let message = cmd.get_flag("message");
+    let input_path = Path::new(input);
+
+    let signature = cmd.get_one::<PathBuf>("signature").expect("required");
+    let signature_data = armor::dearmor(&read_file(signature)?)?.into_owned();
+
+    let key = cmd.get_one::<PathBuf>("key").expect("required");
+    let (public_key, algorithm) = read_signature_key(key, KeyRole::Public, "verification", cmd.get_one::<String>("algorithm"))?;
+
+    let input_name = match message {
+        true => format!("message \"{}\"", input),
+        false => stdio::display(input_path, "<stdin>"),
+    };
+    eprintln!(
+        "Verifying detached signature for {} with signature {} using key {} with algorithm {}",
+        input_name, signature.display(), public_key.describe(key), algorithm
+    );
+
+    if Packet::is_packet(&signature_data) {
+        let packet = Packet::read_from(&mut signature_data.as_slice())?;
+        let context = cmd.get_one::<String>("context");
+        match message {
+            true => verify_packet(&public_key, &packet, &mut input.as_bytes(), context)?,
+            false => verify_packet(&public_key, &packet, &mut stdio::open_input(input_path)?, context)?,
+        }
+        eprintln!("{}", packet);
+        packet.check_expiry()?;
+        eprintln!("Detached signature is valid.");
+        return Ok(());
+    }
+    if cmd.get_one::<String>("context").is_some() {
+        return Err(CryptGuardError::SignatureError("--context needs a signature packet, but this is a bare signature".to_string()));
+    }
+
+    let input_data = match message {
+        true => input.as_bytes().to_owned(),
+        false => stdio::read_input(input_path)?,
+    };
+
+    // Perform the verification
+    let signature_len = signature_data.len();
+    if !verify_with_key(&public_key, signature_data, input_data)? {
+        // A round 3 Dilithium key can't check an ML-DSA signature and vice versa; say so if the size gives it away
+        let candidates = signature_candidates(signature_len);
+        if !candidates.is_empty() && !candidates.contains(&public_key.key_type) {
+            let names: Vec<String> = candidates.iter().map(KeyTypes::to_string).collect();
+            return Err(CryptGuardError::SignatureError(format!(
+                "Detached signature is invalid. It has the size of a {} signature, but the key is {}",
+                names.join(" or "), public_key.key_type
+            )));
+        }
+        return Err(CryptGuardError::SignatureError("Detached signature is invalid.".to_string()));
+    }
+
+    eprintln!("Detached signature is valid.");
+    Ok(())
+}
+
+/// The key an entry names, or `--key` for entries that name none.
+fn manifest_key<'a>(entry: &'a manifest::Entry, default: Option<&'a Path>) -> Option<&'a Path> {
+    entry.key.as_deref().or(default)
+}
+
+/// Checks one manifest entry against its already loaded public key.
+fn verify_manifest_entry(entry: &manifest::Entry, key: &KeyFile) -> Result<(), CryptGuardError> {
+    let signature = armor::dearmor(&read_file(&entry.signature)?)?.into_owned();
+    if Packet::is_packet(&signature) {
+        let packet = Packet::read_from(&mut signature.as_slice())?;
+        verify_packet(key, &packet, &mut fs::File::open(&entry.file)?, None)?;
+        return packet.check_expiry();
+    }
+    match verify_with_key(key, signature, read_file(&entry.file)?)? {
+        true => Ok(()),
+        false => Err(CryptGuardError::SignatureError(format!("signature {} is invalid", entry.signature.display()))),
+    }
+}
+
+fn verify_manifest(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let manifest_path = cmd.get_one::<PathBuf>("MANIFEST").expect("required");
+    let text = String::from_utf8(stdio::read_input(manifest_path)?)
+        .map_err(|_| CryptGuardError::ParseError(format!("{} is not valid UTF-8", stdio::display(manifest_path, "<stdin>"))))?;
+    let base = match stdio::is_stdio(manifest_path) {
+        true => Path::new("."),
+        false => manifest_path.parent().unwrap_or(Path::new(".")),
+    };
+    if manifest::is_checksum_list(&text) {
+        return verify_checksum_manifest(cmd, manifest_path, &text, base);
+    }
+
+    let entries = manifest::parse_signature_list(&text, base)?;
+    if entries.is_empty() {
+        return Err(CryptGuardError::ParseError(format!("{} lists no signatures", stdio::display(manifest_path, "<stdin>"))));
+    }
+
+    let default_key = cmd.get_one::<PathBuf>("key").map(PathBuf::as_path);
+
+    // Every distinct key is read once and shared by all entries naming it; a broken key only fails its own entries
+    let mut keys: HashMap<&Path, Result<KeyFile, String>> = HashMap::new();
+    for entry in &entries {
+        let key = manifest_key(entry, default_key)
+            .ok_or_else(|| CryptGuardError::ParseError(format!("Manifest line {} names no key and --key was not given", entry.line)))?;
+        keys.entry(key).or_insert_with(|| {
+            read_signature_key(key, KeyRole::Public, "verification", None)
+                .map(|(key, _)| key)
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    eprintln!("Verifying {} signatures from {}", entries.len(), stdio::display(manifest_path, "<stdin>"));
+    let results: Vec<Result<(), String>> = entries.par_iter()
+        .map(|entry| {
+            let key = keys[manifest_key(entry, default_key).expect("checked above")].as_ref().map_err(String::clone)?;
+            verify_manifest_entry(entry, key).map_err(|e| e.to_string())
+        })
+        .collect();
+
+    let mut failed = 0;
+    for (entry, result) in entries.iter().zip(&results) {
+        match result {
+            Ok(()) => println!("{}: OK", entry.file.display()),
+            Err(reason) => {
+                failed += 1;
+                println!("{}: FAILED ({})", entry.file.display(), reason);
+            },
+        }
+    }
+
+    if failed > 0 {
+        return Err(CryptGuardError::SignatureError(format!("{} of {} signatures did not verify", failed, entries.len())));
+    }
+    eprintln!("All {} signatures are valid.", entries.len());
+    Ok(())
+}
+
+/// The state of one file listed in, or missing from, a checksum manifest.
+enum FileStatus {
+    Ok,
+    Modified,
+    Missing,
+    Unreadable(String),
+    Extra,
+}
+
+/// Verifies the signature of a SHA256SUMS manifest, then re-hashes the files on disk and reports
+/// every modified, missing or unlisted file.
+fn verify_checksum_manifest(cmd: &ArgMatches, manifest_path: &Path, text: &str, base: &Path) -> Result<(), CryptGuardError> {
+    let signature_path = match (cmd.get_one::<PathBuf>("signature"), stdio::is_stdio(manifest_path)) {
+        (Some(signature), _) => signature.clone(),
+        (None, false) => manifest::signature_path(manifest_path),
+        (None, true) => return Err(CryptGuardError::ParseError("A SHA256SUMS manifest read from stdin needs --signature".to_string())),
+    };
+    let key = cmd.get_one::<PathBuf>("key")
+        .ok_or_else(|| CryptGuardError::ParseError("A SHA256SUMS manifest needs --key to check its signature".to_string()))?;
+    let (public_key, algorithm) = read_signature_key(key, KeyRole::Public, "verification", None)?;
+
+    eprintln!(
+        "Verifying manifest {} with signature {} using key {} with algorithm {}",
+        stdio::display(manifest_path, "<stdin>"), signature_path.display(), public_key.describe(key), algorithm
+    );
+    let signature = armor::dearmor(&read_file(&signature_path)?)?.into_owned();
+    if Packet::is_packet(&signature) {
+        let packet = Packet::read_from(&mut signature.as_slice())?;
+        verify_packet(&public_key, &packet, &mut text.as_bytes(), None)?;
+        eprintln!("{}", packet);
+        packet.check_expiry()?;
+    } else if !verify_with_key(&public_key, signature, text.as_bytes().to_vec())? {
+        return Err(CryptGuardError::SignatureError("Manifest signature is invalid.".to_string()));
+    }
+    eprintln!("Manifest signature is valid.");
+
+    let checksums = manifest::parse_checksums(text)?;
+    let root = cmd.get_one::<PathBuf>("root").map(PathBuf::as_path).unwrap_or(base);
+    let mut report: Vec<(String, FileStatus)> = checksums.par_iter()
+        .map(|checksum| {
+            let status = match manifest::open_listed(root, &checksum.path).and_then(|mut file| digest::sha256(&mut file)) {
+                Ok(digest) if hex::encode(&digest) == checksum.digest => FileStatus::Ok,
+                Ok(_) => FileStatus::Modified,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileStatus::Missing,
+                Err(e) => FileStatus::Unreadable(e.to_string()),
+            };
+            (checksum.path.clone(), status)
+        })
+        .collect();
+
+    let listed: HashSet<&str> = checksums.iter().map(|checksum| checksum.path.as_str()).collect();
+    let exclude = [manifest_path.to_path_buf(), signature_path.clone()];
+    let extra: Vec<String> = manifest::list_files(root, &exclude)?
+        .into_iter()
+        .filter(|path| !listed.contains(path.as_str()))
+        .collect();
+    report.extend(extra.into_iter().map(|path| (path, FileStatus::Extra)));
+
+    let (mut modified, mut missing, mut extra) = (0, 0, 0);
+    for (path, status) in &report {
+        match status {
+            FileStatus::Ok => println!("{}: OK", path),
+            FileStatus::Modified => {
+                modified += 1;
+                println!("{}: FAILED (modified)", path);
+            },
+            FileStatus::Missing => {
+                missing += 1;
+                println!("{}: FAILED (missing)", path);
+            },
+            FileStatus::Unreadable(reason) => {
+                modified += 1;
+                println!("{}: FAILED ({})", path, reason);
+            },
+            FileStatus::Extra => {
+                extra += 1;
+                println!("{}: EXTRA", path);
+            },
+        }
+    }
+
+    if modified + missing + extra > 0 {
+        return Err(CryptGuardError::SignatureError(format!(
+            "{} modified or unreadable, {} missing and {} extra files in {}", modified, missing, extra, root.display()
+        )));
+    }
+    eprintln!("All {} files match the manifest.", checksums.len());
+    Ok(())
+}
+
+fn verify_signed(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let input = cmd.get_one::<String>("input").expect("required");
+    let output = cmd.get_one::<PathBuf>("output").expect("required");
+
+    let message = cmd.get_flag("message");
+    let input_path = Path::new(input);
+    let input_name = match message {
+        true => "<message>".to_string(),
+        false => stdio::display(input_path, "<stdin>"),
+    };
+
+    let key = cmd.get_one::<PathBuf>("key").expect("required");
+    let (public_key, algorithm) = read_signature_key(key, KeyRole::Public, "verification", cmd.get_one::<String>("algorithm"))?;
+
+    let key_name = public_key.describe(key);
+    eprintln!(
+        "Verifying signed data for {} using key {} with algorithm {}",
+        input_name, key_name, algorithm
+    );
+
+    // Only the first bytes are read up front: a packet lets the message be streamed, bare signed data can't be
+    let mut reader: Box<dyn Read> = match message {
+        true => Box::new(std::io::Cursor::new(armor::decode_inline(input, &[])?)),
+        false => armor::reader(stdio::open_input(input_path)?)?,
+    };
+    let mut magic = Vec::new();
+    (&mut reader).take(packet::MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let mut reader = magic.as_slice().chain(reader);
+
+    let context = cmd.get_one::<String>("context");
+    if Packet::is_packet(&magic) {
+        // Files can be read a second time, stdin and inline messages can't
+        let rereadable = match message || stdio::is_stdio(input_path) {
+            true => None,
+            false => Some(input_path),
+        };
+        verify_signed_packet(&public_key, &mut reader, rereadable, output, context)?;
+    } else {
+        if context.is_some() {
+            return Err(CryptGuardError::SignatureError("--context needs a signature packet, but this is bare signed data".to_string()));
+        }
+        let mut input_data = Vec::new();
+        reader.read_to_end(&mut input_data)?;
+
+        // Perform the verification
+        let message = open_with_key(&public_key, input_data)?;
+        stdio::write_output(output, &message)?;
+    }
+
+    eprintln!("Verifying {} to {} using {} with algorithm {}", input_name, stdio::display(output, "<stdout>"), key_name, algorithm);
+    Ok(())
+}
+
+/// Verifies signed data made of a packet and the message, positioned at the packet, and writes out the message.
+///
+/// A file given as `input` is read twice: once to hash and verify the message, then again to copy it, so nothing
+/// unverified reaches the output. The copy is hashed as well and must match, in case the file changed in between.
+/// Without a file to reopen, the message is held in memory instead.
+fn verify_signed_packet<R: Read>(key: &KeyFile, reader: &mut R, input: Option<&Path>, output: &Path, context: Option<&String>) -> Result<(), CryptGuardError> {
+    let packet = Packet::read_from(reader)?;
+    let input = match input {
+        Some(input) => input,
+        None => {
+            let mut message = Vec::new();
+            reader.read_to_end(&mut message)?;
+            verify_packet(key, &packet, &mut message.as_slice(), context)?;
+            eprintln!("{}", packet);
+            packet.check_expiry()?;
+            return Ok(stdio::write_output(output, &message)?);
+        },
+    };
+
+    let digest = packet.hash.digest(reader)?;
+    verify_packet_digest(key, &packet, &digest, context)?;
+    eprintln!("{}", packet);
+    packet.check_expiry()?;
+
+    if fs::canonicalize(input).ok() == fs::canonicalize(output).ok() {
+        return Err(CryptGuardError::ParseError("The message is copied from the input after verifying it, so --output must not be the input file".to_string()));
+    }
+
+    let mut reader = armor::reader(stdio::open_input(input)?)?;
+    Packet::read_from(&mut reader)?;
+    let changed = || CryptGuardError::SignatureError(format!("{} changed while it was being verified", input.display()));
+    // HashingWriter computes SHA3-512, the one hash packets use so far
+    if stdio::is_stdio(output) {
+        let mut writer = digest::HashingWriter::new(stdio::create_output(output)?);
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        return match writer.finalize() == digest {
+            true => Ok(()),
+            false => Err(changed()),
+        };
+    }
+
+    // The copy only replaces `output` once it matches the verified digest, so a failed check
+    // leaves an existing file untouched
+    let mut file = stdio::AtomicFile::create(output, false)?;
+    let mut writer = digest::HashingWriter::new(&mut file);
+    std::io::copy(&mut reader, &mut writer)?;
+    if writer.finalize() != digest {
+        return Err(changed());
+    }
+    file.persist()?;
+    Ok(())
+}
+
+fn verify_cleartext(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let input = cmd.get_one::<PathBuf>("input").expect("required");
+    let output = cmd.get_one::<PathBuf>("output").expect("has a default");
+
+    let key = cmd.get_one::<PathBuf>("key").expect("required");
+    let (public_key, algorithm) = read_signature_key(key, KeyRole::Public, "verification", cmd.get_one::<String>("algorithm"))?;
+    eprintln!(
+        "Verifying cleartext signature of {} using key {} with algorithm {}",
+        stdio::display(input, "<stdin>"), public_key.describe(key), algorithm
+    );
+
+    let (text, signature) = cleartext::decode(&stdio::read_input(input)?)?;
+    if !Packet::is_packet(&signature) {
+        return Err(CryptGuardError::ParseError("The signature block of a cleartext signature must hold a signature packet".to_string()));
+    }
+    let packet = Packet::read_from(&mut signature.as_slice())?;
+    verify_packet(&public_key, &packet, &mut cleartext::canonicalize(&text).as_slice(), cmd.get_one::<String>("context"))?;
+    eprintln!("{}", packet);
+    packet.check_expiry()?;
+
+    // Only the text that was verified is written, never the input as given
+    stdio::write_output(output, format!("{}\n", text).as_bytes())?;
+    eprintln!("Cleartext signature is valid.");
+    Ok(())
+}
+
+/// Key types whose detached signatures are exactly `len` bytes long.
+fn signature_candidates(len: usize) -> Vec<KeyTypes> {
+    KeyTypes::all().filter(|key_type| key_type.signature_len() == Some(len)).collect()
+}
+
+fn inspect(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let path = cmd.get_one::<PathBuf>("FILE").expect("required");
+    let data = stdio::read_input(path)?;
+    if cleartext::is_cleartext(&data) {
+        let (text, signature) = cleartext::decode(&data)?;
+        println!("Type:        cleartext-signed text, {} lines", text.lines().count());
+        if Packet::is_packet(&signature) {
+            println!("{}", Packet::read_from(&mut signature.as_slice())?);
+        }
+        println!("The fields are unverified until checked with verify cleartext");
+        return Ok(());
+    }
+    let data = armor::dearmor(&data)?;
+
+    if data.starts_with(keyfile::MAGIC) {
+        let key = KeyFile::from_bytes(&data)?;
+        println!("Type:        {} {} key", key.key_type, key.role);
+        println!("Format:      {}", key.key_type.format());
+        return Ok(());
+    }
+
+    if data.starts_with(container::MAGIC) {
+        let header = Header::read_from(&mut &data[..])?;
+        println!("Type:        encrypted container, {}", header.algorithm);
+        let kems: Vec<(Kem, usize)> = match header.has_recipients() {
+            true => header.recipients.iter().map(|stanza| (stanza.kem, stanza.key_size)).collect(),
+            false => vec![(header.kem, header.key_size)],
+        };
+        for (kem, key_size) in kems {
+            let format = KeyTypes::all()
+                .find(|key_type| key_type.kem() == Some(kem) && key_type.key_size() == key_size)
+                .map_or("unknown", |key_type| key_type.format());
+            println!("Encrypted to: {} - {}", kem.name(key_size), format);
+        }
+        return Ok(());
+    }
+
+    if Packet::is_packet(&data) {
+        let mut reader = &data[..];
+        let packet = Packet::read_from(&mut reader)?;
+        match reader.is_empty() {
+            true => println!("Type:        detached signature packet"),
+            false => println!("Type:        signed data with a signature packet, {} byte message", reader.len()),
+        }
+        println!("{}", packet);
+        println!("Format:      {}", packet.key_type.format());
+        println!("The fields are unverified until checked with verify");
+        return Ok(());
+    }
+
+    // Bare signatures carry no header, so all that is left is their size
+    let candidates = signature_candidates(data.len());
+    println!("Type:        detached signature or signed data, {} bytes", data.len());
+    match candidates.len() {
+        0 => println!("Format:      unknown; Falcon signatures and signed data have no fixed size, verify them against a key instead"),
+        _ => {
+            for key_type in &candidates {
+                println!("Candidate:   {} - {}", key_type, key_type.format());
+            }
+            if candidates.len() > 1 {
+                println!("The size is ambiguous; only verifying against the key tells them apart");
+            }
+        },
+    }
+    Ok(())
+}
+
+fn key_info(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let path = cmd.get_one::<PathBuf>("FILE").expect("required");
+    let path = keyring::resolve(path, KeyRole::Public).or_else(|_| keyring::resolve(path, KeyRole::Secret))?;
+    let key = KeyFile::read(&path)?;
+
+    println!("File:        {}", path.display());
+    println!("Algorithm:   {}", key.key_type);
+    println!("Format:      {}", key.key_type.format());
+    println!("Security:    NIST level {}", key.key_type.security_level());
+    println!("Role:        {} key", key.role);
+    println!("Size:        {} bytes", key.key_len());
+    if key.role == KeyRole::Secret {
+        println!("Protected:   {}", if key.is_sealed() { "yes, passphrase (argon2id)" } else { "no" });
+    }
+    match key.fingerprint() {
+        Some(fingerprint) => println!("Fingerprint: {}", fingerprint),
+        None => println!("Fingerprint: unknown, the secret key does not expose its public key"),
+    }
+    Ok(())
+}
+
+fn key_import(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let path = cmd.get_one::<PathBuf>("FILE").expect("required");
+    let key = KeyFile::read(path)?;
+    let name = match cmd.get_one::<String>("name") {
+        Some(name) => name.clone(),
+        None => path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(str::to_string)
+            .ok_or_else(|| CryptGuardError::ParseError(format!("Can't derive a name from {}; pass --name", path.display())))?,
+    };
+
+    let keyring = Keyring::open()?;
+    let mut identity = match keyring.get(&name) {
+        Some(identity) => {
+            if let (Some(existing), Some(imported)) = (identity.fingerprint(), key.fingerprint()) {
+                if existing != imported {
+                    return Err(CryptGuardError::ParseError(format!("The keyring entry {} holds a different key (fingerprint {})", name, existing)));
+                }
+            }
+            if identity.has(key.role) {
+                return Err(CryptGuardError::ParseError(format!("The keyring entry {} already has a {} key", name, key.role)));
+            }
+            identity
+        },
+        None => keyring.create(&name, None)?,
+    };
+    if let Some(label) = cmd.get_one::<String>("label") {
+        identity.set_label(label)?;
+    }
+
+    key.write(&identity.key_path(key.role), false)?;
+    // A secret key brings its public half along whenever it can be recovered
+    if let (KeyRole::Secret, false, Some(public)) = (key.role, identity.has(KeyRole::Public), key.public_key()) {
+        KeyFile::new(key.key_type, KeyRole::Public, public.to_vec()).write(&identity.key_path(KeyRole::Public), false)?;
+    }
+
+    eprintln!("Imported {} key {} as {}", key.role, key.describe(path), name);
+    Ok(())
+}
+
+fn key_export(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let query = cmd.get_one::<String>("KEY").expect("required");
+    let output = cmd.get_one::<PathBuf>("output").expect("required");
+    let role = if cmd.get_flag("secret") { KeyRole::Secret } else { KeyRole::Public };
+
+    let identity = Keyring::open()?.find(query, role)?;
+    let key = identity.read(role)?;
+    key.write(output, cmd.get_flag("armor"))?;
+
+    eprintln!("Exported {} key of {} to {}", role, identity.name, stdio::display(output, "<stdout>"));
+    Ok(())
+}
+
+fn key_list() -> Result<(), CryptGuardError> {
+    let keyring = Keyring::open()?;
+    let identities = keyring.identities()?;
+    if identities.is_empty() {
+        eprintln!("The keyring at {} is empty", keyring.root().display());
+        return Ok(());
+    }
+
+    println!("{:<20} {:<12} {:<8} {:<64} LABEL", "NAME", "ALGORITHM", "KEYS", "FINGERPRINT");
+    for identity in identities {
+        let algorithm = identity.primary_key().map(|key| key.key_type.to_string()).unwrap_or_else(|| "-".to_string());
+        let keys = match (identity.has(KeyRole::Public), identity.has(KeyRole::Secret)) {
+            (true, true) => "pub+sec",
+            (true, false) => "pub",
+            (false, true) => "sec",
+            (false, false) => "-",
+        };
+        let fingerprint = identity.fingerprint().unwrap_or_else(|| "unknown".to_string());
+        println!("{:<20} {:<12} {:<8} {:<64} {}", identity.name, algorithm, keys, fingerprint, identity.label.as_deref().unwrap_or(""));
+    }
+    Ok(())
+}
+
+fn key_delete(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let name = cmd.get_one::<String>("NAME").expect("required");
+    let keyring = Keyring::open()?;
+    let identity = keyring.get(name)
+        .ok_or_else(|| CryptGuardError::ParseError(format!("The keyring has no entry named {}", name)))?;
+
+    if identity.has(KeyRole::Secret) && !cmd.get_flag("force") {
+        return Err(CryptGuardError::ParseError(format!("{} holds a secret key; pass --force to delete it", name)));
+    }
+
+    keyring.delete(identity)?;
+    eprintln!("Deleted {} from the keyring", name);
+    Ok(())
+}
+
+fn key_public(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let path = &keyring::resolve(cmd.get_one::<PathBuf>("from").expect("required"), KeyRole::Secret)?;
+    let output = cmd.get_one::<PathBuf>("output").expect("required");
+    let mut secret_key = KeyFile::read(path)?;
+    secret_key.require(KeyRole::Secret, "key public", |_| true)?;
+
+    // Only sealed Kyber keys without an embedded copy need to be unlocked first
+    if secret_key.public_key().is_none() && secret_key.key_type.is_kyber() {
+        unlock_secret_key(&mut secret_key, path, cmd)?;
+    }
+    let public = secret_key.public_key()
+        .ok_or_else(|| CryptGuardError::ParseError(format!(
+            "{} is a {} secret key without an embedded public key; {} can't recompute the public key from the secret key, so it has to come from the original .pub file",
+            path.display(), secret_key.key_type, secret_key.key_type
+        )))?
+        .to_vec();
+
+    let public_key = KeyFile::new(secret_key.key_type, KeyRole::Public, public);
+    public_key.write(output, cmd.get_flag("armor"))?;
+    eprintln!("Wrote public key {} extracted from {}", public_key.describe(output), path.display());
+    Ok(())
+}
+
+fn change_passphrase(cmd: &ArgMatches) -> Result<(), CryptGuardError> {
+    let key = &keyring::resolve(cmd.get_one::<PathBuf>("key").expect("required"), KeyRole::Secret)?;
+    let armored = armor::is_armored(&read_file(key)?);
+    let mut secret_key = KeyFile::read(key)?;
+    secret_key.require(KeyRole::Secret, "change-passphrase", |_| true)?;
+    unlock_secret_key(&mut secret_key, key, cmd)?;
+
+    if cmd.get_flag("remove") {
+        secret_key.write(key, armored)?;
+        eprintln!("Removed the passphrase from {}", key.display());
+        return Ok(());
+    }
+
+    let source = passphrase::from_args(cmd, "new-passphrase-env", "new-passphrase-fd");
+    let passphrase = passphrase::read(&source, "New passphrase", true)?;
+    secret_key.seal(&passphrase)?;
+    secret_key.write(key, armored)?;
+
+    eprintln!("Changed the passphrase of {}", key.display());
+    Ok(())
+}
+
+fn parse_cli(matches: ArgMatches) -> Result<(), CryptGuardError> {
+    match matches.subcommand() {
+        Some(("keygen", sub_matches)) => keygen(sub_matches),
+        Some(("encrypt", sub_matches)) => encrypt(sub_matches),
+        Some(("decrypt", sub_matches)) => decrypt(sub_matches),
+        Some(("sign", sub_matches)) => sign(sub_matches),
+        Some(("inspect", sub_matches)) => inspect(sub_matches),
+        Some(("key", sub_matches)) => match sub_matches.subcommand() {
+            Some(("info", cmd)) => key_info(cmd),
+            Some(("import", cmd)) => key_import(cmd),
+            Some(("export", cmd)) => key_export(cmd),
+            Some(("list", _)) => key_list(),
+            Some(("delete", cmd)) => key_delete(cmd),
+            Some(("public", cmd)) => key_public(cmd),
+            Some(("change-passphrase", cmd)) => change_passphrase(cmd),
+            _ => Err(CryptGuardError::ParseError("Unknown key operation".to_string())),
+        },
+        Some(("verify", sub_matches)) => match sub_matches.subcommand() {
+            Some(("detached", cmd)) => verify_detached(cmd),
+            Some(("signed", cmd)) => verify_signed(cmd),
+            Some(("cleartext", cmd)) => verify_cleartext(cmd),
+            Some(("manifest", cmd)) => verify_manifest(cmd),
+            _ => Err(CryptGuardError::ParseError("Unknown verify applet".to_string())),
+        },
+        _ => Err(CryptGuardError::ParseError("Unknown command".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::crypt_guard::KDF::*;
+
+    fn composite_pair() -> (KeyFile, KeyFile) {
+        let (public, secret) = DilithiumKeypair!(2);
+        let (public, secret) = composite::keypair(public, secret);
+        (KeyFile::new(KeyTypes::Ed25519Dilithium2, KeyRole::Public, public), KeyFile::new(KeyTypes::Ed25519Dilithium2, KeyRole::Secret, secret))
+    }
+
+    #[test]
+    fn composite_detached_round_trip() {
+        let (public, secret) = composite_pair();
+        let signature = sign_with_key(&secret, b"data".to_vec(), &SignatureType::Detached).unwrap();
+        assert!(verify_with_key(&public, signature.clone(), b"data".to_vec()).unwrap());
+        assert!(!verify_with_key(&public, signature, b"other".to_vec()).unwrap());
+    }
+
+    #[test]
+    fn composite_needs_both_halves() {
+        let (public, secret) = composite_pair();
+        let signature = sign_with_key(&secret, b"data".to_vec(), &SignatureType::Detached).unwrap();
+
+        // Byte 0 lies in the Ed25519 half, the last byte in the Dilithium half
+        for index in [0, signature.len() - 1] {
+            let mut flipped = signature.clone();
+            flipped[index] ^= 1;
+            assert!(!verify_with_key(&public, flipped, b"data".to_vec()).unwrap(), "byte {} flipped", index);
+        }
+    }
+
+    #[test]
+    fn composite_signed_data_round_trip() {
+        let (public, secret) = composite_pair();
+        let signed = sign_with_key(&secret, b"data".to_vec(), &SignatureType::SignedData).unwrap();
+        assert_eq!(open_with_key(&public, signed.clone()).unwrap(), b"data");
 
+        let mut flipped = signed;
+        flipped[0] ^= 1;
+        assert!(matches!(open_with_key(&public, flipped), Err(CryptGuardError::SignatureError(_))));
     }
 }
@@ -0,0 +1,263 @@
+use std::{fmt::Write, fs, io, path::{Component, Path, PathBuf}};
+use crate::CryptGuardError;
+
+/// Length of a hex encoded SHA-256 digest.
+const SHA256_HEX_LEN: usize = 64;
+
+/// One line of a signature list: a file, its detached signature and the public key to check it with.
+#[derive(Debug)]
+pub struct Entry {
+    pub line: usize,
+    pub file: PathBuf,
+    pub signature: PathBuf,
+    /// Key file or keyring reference; `None` means the `--key` given on the command line.
+    pub key: Option<PathBuf>,
+}
+
+/// Parses a signature list with one `FILE SIGNATURE [KEY]` entry per line.
+///
+/// Fields are separated by tabs if the line contains one, otherwise by whitespace, so paths
+/// containing spaces need tabs. Blank lines and lines starting with `#` are skipped. Relative
+/// file and signature paths are taken relative to `base`, the directory of the list.
+pub fn parse_signature_list(text: &str, base: &Path) -> Result<Vec<Entry>, CryptGuardError> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = match line.contains('\t') {
+            true => line.split('\t').map(str::trim).filter(|field| !field.is_empty()).collect(),
+            false => line.split_whitespace().collect(),
+        };
+        let (file, signature, key) = match fields.as_slice() {
+            [file, signature] => (file, signature, None),
+            [file, signature, key] => (file, signature, Some(relative_to(base, key))),
+            _ => return Err(CryptGuardError::ParseError(format!(
+                "Manifest line {}: expected FILE SIGNATURE [KEY], found {} fields", line_number, fields.len()
+            ))),
+        };
+        entries.push(Entry { line: line_number, file: base.join(file), signature: base.join(signature), key });
+    }
+    Ok(entries)
+}
+
+/// `base/reference` if that file exists, otherwise `reference` as given, which may be a keyring name.
+fn relative_to(base: &Path, reference: &str) -> PathBuf {
+    let candidate = base.join(reference);
+    match candidate.exists() {
+        true => candidate,
+        false => PathBuf::from(reference),
+    }
+}
+
+/// One line of a `SHA256SUMS`-style checksum manifest.
+#[derive(Debug)]
+pub struct Checksum {
+    /// Lowercase hex SHA-256 of the file.
+    pub digest: String,
+    /// Path relative to the manifest's root directory, with `/` separators.
+    pub path: String,
+}
+
+/// Where the detached signature of a checksum manifest is kept: `<manifest>.sig`.
+pub fn signature_path(manifest: &Path) -> PathBuf {
+    let mut path = manifest.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Splits a `sha256sum` line, `<hex>  <path>` or `<hex> *<path>`, into digest and path.
+fn split_checksum_line(line: &str) -> Option<(&str, &str)> {
+    let (digest, rest) = line.split_at_checked(SHA256_HEX_LEN)?;
+    let path = rest.strip_prefix("  ").or_else(|| rest.strip_prefix(" *"))?;
+    match digest.bytes().all(|byte| byte.is_ascii_hexdigit()) && !path.is_empty() {
+        true => Some((digest, path)),
+        false => None,
+    }
+}
+
+/// Whether `text` is a checksum manifest rather than a signature list, judged by its first entry.
+pub fn is_checksum_list(text: &str) -> bool {
+    text.lines()
+        .map(str::trim_end)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .is_some_and(|line| split_checksum_line(line).is_some())
+}
+
+/// Parses a checksum manifest. Paths must stay inside the root directory: absolute paths and
+/// `..` components are rejected.
+pub fn parse_checksums(text: &str) -> Result<Vec<Checksum>, CryptGuardError> {
+    let mut checksums = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (digest, path) = split_checksum_line(line).ok_or_else(|| CryptGuardError::ParseError(format!(
+            "Manifest line {}: expected a SHA-256 digest, two spaces and a path", line_number
+        )))?;
+        if !Path::new(path).components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(CryptGuardError::ParseError(format!("Manifest line {}: {} leaves the manifest directory", line_number, path)));
+        }
+        checksums.push(Checksum { digest: digest.to_ascii_lowercase(), path: path.to_string() });
+    }
+    Ok(checksums)
+}
+
+/// Formats `(digest, path)` pairs as `sha256sum` would, one `<hex>  <path>` line each.
+pub fn format_checksums(checksums: &[(String, String)]) -> String {
+    checksums.iter().fold(String::new(), |mut out, (digest, path)| {
+        let _ = writeln!(out, "{}  {}", digest, path);
+        out
+    })
+}
+
+/// Lists every regular file and symbolic link below `root`, recursively, as sorted `/`-separated
+/// relative paths. Links are listed rather than followed, so `open_listed` can refuse them instead
+/// of silently leaving them out. Files in `exclude` (such as the manifest itself) are left out.
+pub fn list_files(root: &Path, exclude: &[PathBuf]) -> Result<Vec<String>, CryptGuardError> {
+    let root = fs::canonicalize(root)?;
+    let exclude: Vec<PathBuf> = exclude.iter().filter_map(|path| absolute(path)).collect();
+    let mut files = Vec::new();
+    walk(&root, "", &exclude, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk(dir: &Path, prefix: &str, exclude: &[PathBuf], files: &mut Vec<String>) -> Result<(), CryptGuardError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string()
+            .map_err(|name| CryptGuardError::ParseError(format!("{} is not a valid UTF-8 file name", dir.join(name).display())))?;
+        let relative = match prefix.is_empty() {
+            true => name,
+            false => format!("{}/{}", prefix, name),
+        };
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), &relative, exclude, files)?;
+        } else if (file_type.is_file() || file_type.is_symlink()) && !exclude.contains(&entry.path()) {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// `path` with its directory canonicalized, so it can be compared with paths found by `walk`.
+/// The file itself need not exist yet; `None` if its directory doesn't.
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::canonicalize(parent).ok().map(|parent| parent.join(name))
+}
+
+/// Opens `root/path` for hashing, translating the manifest's `/` separators. Manifests cover
+/// regular files only, so a symbolic link anywhere along the path is an error.
+pub fn open_listed(root: &Path, path: &str) -> io::Result<fs::File> {
+    let mut full = root.to_path_buf();
+    for component in path.split('/') {
+        full.push(component);
+        if fs::symlink_metadata(&full)?.file_type().is_symlink() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "symbolic links can't be listed in a manifest"));
+        }
+    }
+    fs::File::open(full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signature_lists() {
+        let base = Path::new("/nonexistent/base");
+        let text = "# release files\n\na.tar a.tar.sig\nb c.tar\tb c.tar.sig\talice\n";
+        let entries = parse_signature_list(text, base).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].line, &entries[0].file, &entries[0].signature), (3, &base.join("a.tar"), &base.join("a.tar.sig")));
+        assert_eq!(entries[0].key, None);
+        assert_eq!((entries[1].line, &entries[1].file), (4, &base.join("b c.tar")));
+        assert_eq!(entries[1].key, Some(PathBuf::from("alice")));
+    }
+
+    #[test]
+    fn rejects_lines_with_the_wrong_field_count() {
+        for text in ["a.tar\n", "a.tar a.tar.sig alice extra\n"] {
+            assert!(matches!(parse_signature_list(text, Path::new(".")), Err(CryptGuardError::ParseError(_))), "{:?}", text);
+        }
+    }
+
+    const DIGEST: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crypt_guard-manifest-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"a").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), b"b").unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_checksums() {
+        let text = format!("# comment\n{}  a.txt\n{} *sub/b c.txt\n", DIGEST.to_uppercase(), DIGEST);
+        assert!(is_checksum_list(&text));
+        assert!(!is_checksum_list("a.tar a.tar.sig\n"));
+
+        let checksums = parse_checksums(&text).unwrap();
+        let parsed: Vec<(&str, &str)> = checksums.iter().map(|checksum| (checksum.digest.as_str(), checksum.path.as_str())).collect();
+        assert_eq!(parsed, [(DIGEST, "a.txt"), (DIGEST, "sub/b c.txt")]);
+
+        let pairs = [(DIGEST.to_string(), "a.txt".to_string())];
+        assert_eq!(parse_checksums(&format_checksums(&pairs)).unwrap()[0].path, "a.txt");
+    }
+
+    #[test]
+    fn rejects_malformed_checksum_lines() {
+        for line in [&DIGEST[1..], "zz", &format!("{} a.txt", DIGEST), &format!("{}  ", DIGEST)] {
+            assert!(matches!(parse_checksums(line), Err(CryptGuardError::ParseError(_))), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_root() {
+        for path in ["../etc/passwd", "sub/../../x", "/etc/passwd", "./a.txt"] {
+            let line = format!("{}  {}", DIGEST, path);
+            assert!(matches!(parse_checksums(&line), Err(CryptGuardError::ParseError(_))), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn lists_files_and_excludes() {
+        let dir = scratch("list");
+        fs::write(dir.join("SHA256SUMS"), b"").unwrap();
+        assert_eq!(list_files(&dir, &[dir.join("SHA256SUMS")]).unwrap(), ["a.txt", "sub/b.txt"]);
+        assert_eq!(open_listed(&dir, "sub/b.txt").unwrap().metadata().unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_and_refuses_symlinks() {
+        let dir = scratch("symlink");
+        std::os::unix::fs::symlink("a.txt", dir.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("sub", dir.join("linked-dir")).unwrap();
+
+        assert_eq!(list_files(&dir, &[]).unwrap(), ["a.txt", "link.txt", "linked-dir", "sub/b.txt"]);
+        for path in ["link.txt", "linked-dir/b.txt"] {
+            assert_eq!(open_listed(&dir, path).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{}", path);
+        }
+        assert_eq!(open_listed(&dir, "gone.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use fips204::traits::{KeyGen, SerDes, Signer, Verifier};
+use ::crypt_guard::error::CryptError;
+
+/// FIPS 204 context string; crypt_guard signs with the empty context.
+const CONTEXT: &[u8] = b"";
+
+/// Parses a byte slice into one of the fixed-size fips204 types.
+macro_rules! ml_dsa_decode {
+    ($type:ty, $bytes:expr, $what:literal) => {
+        <$type>::try_from_bytes($bytes.try_into().map_err(|_| CryptError::new(concat!("Invalid ML-DSA ", $what, " length")))?)
+            .map_err(CryptError::new)?
+    };
+}
+
+/// Length of a signature for the parameter set `level` (44, 65 or 87).
+pub fn signature_len(level: usize) -> Option<usize> {
+    match level {
+        87 => Some(fips204::ml_dsa_87::SIG_LEN),
+        65 => Some(fips204::ml_dsa_65::SIG_LEN),
+        44 => Some(fips204::ml_dsa_44::SIG_LEN),
+        _ => None,
+    }
+}
+
+/// Generates an ML-DSA key pair in the FIPS 204 encoding, returning `(public, secret)`.
+pub fn keypair(level: usize) -> Result<(Vec<u8>, Vec<u8>), CryptError> {
+    match level {
+        87 => fips204::ml_dsa_87::KG::try_keygen().map(|(pk, sk)| (pk.into_bytes().to_vec(), sk.into_bytes().to_vec())),
+        65 => fips204::ml_dsa_65::KG::try_keygen().map(|(pk, sk)| (pk.into_bytes().to_vec(), sk.into_bytes().to_vec())),
+        44 => fips204::ml_dsa_44::KG::try_keygen().map(|(pk, sk)| (pk.into_bytes().to_vec(), sk.into_bytes().to_vec())),
+        _ => return Err(CryptError::new("Invalid ML-DSA parameter set")),
+    }.map_err(CryptError::new)
+}
+
+/// Detached ML-DSA signature over `message`.
+pub fn sign(level: usize, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, CryptError> {
+    match level {
+        87 => ml_dsa_decode!(fips204::ml_dsa_87::PrivateKey, secret_key, "secret key").try_sign(message, CONTEXT).map(|sig| sig.to_vec()),
+        65 => ml_dsa_decode!(fips204::ml_dsa_65::PrivateKey, secret_key, "secret key").try_sign(message, CONTEXT).map(|sig| sig.to_vec()),
+        44 => ml_dsa_decode!(fips204::ml_dsa_44::PrivateKey, secret_key, "secret key").try_sign(message, CONTEXT).map(|sig| sig.to_vec()),
+        _ => return Err(CryptError::new("Invalid ML-DSA parameter set")),
+    }.map_err(CryptError::new)
+}
+
+/// Checks a detached ML-DSA signature; a signature of the wrong length is simply invalid.
+pub fn verify(level: usize, public_key: &[u8], signature: &[u8], message: &[u8]) -> Result<bool, CryptError> {
+    let is_valid = match level {
+        87 => match signature.try_into() {
+            Ok(signature) => ml_dsa_decode!(fips204::ml_dsa_87::PublicKey, public_key, "public key").verify(message, signature, CONTEXT),
+            Err(_) => false,
+        },
+        65 => match signature.try_into() {
+            Ok(signature) => ml_dsa_decode!(fips204::ml_dsa_65::PublicKey, public_key, "public key").verify(message, signature, CONTEXT),
+            Err(_) => false,
+        },
+        44 => match signature.try_into() {
+            Ok(signature) => ml_dsa_decode!(fips204::ml_dsa_44::PublicKey, public_key, "public key").verify(message, signature, CONTEXT),
+            Err(_) => false,
+        },
+        _ => return Err(CryptError::new("Invalid ML-DSA parameter set")),
+    };
+    Ok(is_valid)
+}
+
+/// Signed data in the same `signature || message` layout the Falcon and Dilithium macros produce.
+pub fn sign_message(level: usize, secret_key: &[u8], message: &[u8]) -> Result<Vec<u8>, CryptError> {
+    Ok([sign(level, secret_key, message)?.as_slice(), message].concat())
+}
+
+/// Verifies signed data from `sign_message` and returns the message.
+pub fn open_message(level: usize, public_key: &[u8], signed: &[u8]) -> Result<Vec<u8>, CryptError> {
+    let signature_len = signature_len(level).ok_or_else(|| CryptError::new("Invalid ML-DSA parameter set"))?;
+    if signed.len() < signature_len {
+        return Err(CryptError::new("Signed data is shorter than an ML-DSA signature"));
+    }
+
+    let (signature, message) = signed.split_at(signature_len);
+    match verify(level, public_key, signature, message)? {
+        true => Ok(message.to_vec()),
+        false => Err(CryptError::new("ML-DSA signature verification failed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detached_round_trip() {
+        for level in [44, 65, 87] {
+            let (public, secret) = keypair(level).unwrap();
+            let signature = sign(level, &secret, b"data").unwrap();
+            assert_eq!(Some(signature.len()), signature_len(level));
+            assert!(verify(level, &public, &signature, b"data").unwrap());
+            assert!(!verify(level, &public, &signature, b"other").unwrap());
+            assert!(!verify(level, &public, &signature[1..], b"data").unwrap());
+        }
+    }
+
+    #[test]
+    fn signed_data_round_trip() {
+        let (public, secret) = keypair(65).unwrap();
+        let mut signed = sign_message(65, &secret, b"data").unwrap();
+        assert_eq!(open_message(65, &public, &signed).unwrap(), b"data");
+
+        signed[0] ^= 1;
+        assert!(open_message(65, &public, &signed).is_err());
+        assert!(open_message(65, &public, &signed[..10]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_keys_and_parameter_sets() {
+        let (public, _) = keypair(44).unwrap();
+        assert!(sign(44, &[0; 10], b"data").is_err());
+        assert!(verify(44, &public[1..], &vec![0; fips204::ml_dsa_44::SIG_LEN], b"data").is_err());
+        assert!(keypair(42).is_err());
+        assert_eq!(signature_len(42), None);
+    }
+}
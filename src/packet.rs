@@ -0,0 +1,311 @@
+use std::{fmt, io::Read, time::{SystemTime, UNIX_EPOCH}};
+use crate::{container::{read_u16, read_u32, read_u8, read_vec, MAX_SIGNATURE_LEN}, digest, keyfile::KeyFile, CryptGuardError, KeyTypes};
+
+/// Magic bytes at the start of every signature packet written by `sign`.
+pub const MAGIC: &[u8; 5] = b"CGSIG";
+/// Current signature packet format version.
+pub const VERSION: u8 = 1;
+/// Length of a key fingerprint, a SHA3-256 digest.
+const FINGERPRINT_LEN: usize = 32;
+
+/// Digest of the signed data that goes into the signature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Sha3_512,
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HashAlgorithm::Sha3_512 => "sha3-512",
+            }
+        )
+    }
+}
+
+impl HashAlgorithm {
+    fn id(&self) -> u8 {
+        match self {
+            HashAlgorithm::Sha3_512 => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, CryptGuardError> {
+        match id {
+            1 => Ok(HashAlgorithm::Sha3_512),
+            _ => Err(CryptGuardError::ParseError(format!("Invalid hash algorithm identifier: {}", id))),
+        }
+    }
+
+    pub fn digest<R: Read>(&self, reader: &mut R) -> Result<Vec<u8>, CryptGuardError> {
+        match self {
+            HashAlgorithm::Sha3_512 => Ok(digest::sha3_512(reader)?),
+        }
+    }
+}
+
+/// A signature together with who made it, when, until when it is valid and what it is for.
+///
+/// The signature covers the canonical encoding of all other fields followed by the digest of
+/// the data, so none of the metadata can be changed without invalidating it. A detached packet
+/// is stored on its own; signed data is the packet followed by the message.
+#[derive(Debug)]
+pub struct Packet {
+    pub key_type: KeyTypes,
+    pub hash: HashAlgorithm,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    /// Seconds since the Unix epoch; `None` if the signature never expires.
+    pub expires: Option<u64>,
+    /// SHA3-256 of the signer's public key, as shown by `key info`.
+    pub fingerprint: [u8; FINGERPRINT_LEN],
+    /// Domain-separation string, such as `release` or `backup`; may be empty.
+    pub context: String,
+    pub signature: Vec<u8>,
+}
+
+impl Packet {
+    /// An unsigned packet for `key`, created now and valid for `lifetime` seconds if given.
+    pub fn new(key: &KeyFile, context: String, lifetime: Option<u64>) -> Result<Self, CryptGuardError> {
+        if context.len() > u16::MAX as usize {
+            return Err(CryptGuardError::ParseError(format!("The signature context is longer than {} bytes", u16::MAX)));
+        }
+        let fingerprint = key.fingerprint()
+            .and_then(|fingerprint| hex::decode(fingerprint).ok())
+            .and_then(|fingerprint| fingerprint.try_into().ok())
+            .ok_or_else(|| CryptGuardError::ParseError("The key has no embedded public key, so its fingerprint is unknown; regenerate it with keygen".to_string()))?;
+        let created = now();
+        let expires = lifetime.map(|lifetime| created.saturating_add(lifetime));
+        Ok(Packet { key_type: key.key_type, hash: HashAlgorithm::Sha3_512, created, expires, fingerprint, context, signature: Vec::new() })
+    }
+
+    pub fn is_packet(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Canonical encoding of every field except the signature.
+    fn signed_fields(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(self.key_type.id());
+        buf.push(self.hash.id());
+        buf.extend_from_slice(&self.created.to_be_bytes());
+        buf.extend_from_slice(&self.expires.unwrap_or(0).to_be_bytes());
+        buf.extend_from_slice(&self.fingerprint);
+        buf.extend_from_slice(&(self.context.len() as u16).to_be_bytes());
+        buf.extend_from_slice(self.context.as_bytes());
+        buf
+    }
+
+    /// The bytes the signature is made over: the encoded fields followed by the data digest.
+    pub fn message(&self, digest: &[u8]) -> Vec<u8> {
+        [self.signed_fields().as_slice(), digest].concat()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.signed_fields();
+        buf.extend_from_slice(&(self.signature.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.signature);
+        buf
+    }
+
+    /// Parses a packet from the start of `reader`, leaving it at the message of signed data.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, CryptGuardError> {
+        let magic = read_vec(reader, MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(CryptGuardError::ParseError("Input is not a crypt_guard signature packet".to_string()));
+        }
+
+        let version = read_u8(reader)?;
+        if version == 0 || version > VERSION {
+            return Err(CryptGuardError::ParseError(format!("Unsupported signature packet version: {}", version)));
+        }
+
+        let key_type = KeyTypes::from_id(read_u8(reader)?)?;
+        let hash = HashAlgorithm::from_id(read_u8(reader)?)?;
+        let created = read_u64(reader)?;
+        let expires = match read_u64(reader)? {
+            0 => None,
+            expires => Some(expires),
+        };
+        let fingerprint = read_vec(reader, FINGERPRINT_LEN)?.try_into().expect("read FINGERPRINT_LEN bytes");
+        let context_len = read_u16(reader)? as usize;
+        let context = String::from_utf8(read_vec(reader, context_len)?)
+            .map_err(|_| CryptGuardError::ParseError("The signature context is not valid UTF-8".to_string()))?;
+        let signature_len = read_u32(reader)? as usize;
+        if signature_len > MAX_SIGNATURE_LEN {
+            return Err(CryptGuardError::ParseError(format!("Signature packet is corrupted: a {} byte signature is implausible", signature_len)));
+        }
+        let signature = read_vec(reader, signature_len)?;
+
+        Ok(Packet { key_type, hash, created, expires, fingerprint, context, signature })
+    }
+
+    /// Checks that `key` is the one the packet names as its signer.
+    pub fn check_signer(&self, key: &KeyFile) -> Result<(), CryptGuardError> {
+        if key.key_type != self.key_type {
+            return Err(CryptGuardError::SignatureError(format!("The signature was made with a {} key, not {}", self.key_type, key.key_type)));
+        }
+        if key.fingerprint() != Some(hex::encode(self.fingerprint)) {
+            return Err(CryptGuardError::SignatureError(format!("The signature was made by key {}, not by this key", hex::encode(self.fingerprint))));
+        }
+        Ok(())
+    }
+
+    /// Fails if the signature has expired. Only meaningful once the signature has been verified.
+    pub fn check_expiry(&self) -> Result<(), CryptGuardError> {
+        match self.expires {
+            Some(expires) if now() >= expires => Err(CryptGuardError::SignatureError(format!("The signature expired on {}", format_time(expires)))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Signer:      {} ({})", hex::encode(self.fingerprint), self.key_type)?;
+        writeln!(f, "Created:     {}", format_time(self.created))?;
+        match self.expires {
+            Some(expires) => writeln!(f, "Expires:     {}", format_time(expires))?,
+            None => writeln!(f, "Expires:     never")?,
+        }
+        writeln!(f, "Context:     {}", self.context)?;
+        write!(f, "Hash:        {}", self.hash)
+    }
+}
+
+/// Parses a signature lifetime such as `3600`, `90m`, `12h`, `30d`, `2w` or `1y` into seconds.
+pub fn parse_lifetime(input: &str) -> Result<u64, CryptGuardError> {
+    let invalid = || CryptGuardError::ParseError(format!("Invalid signature lifetime: {} (expected e.g. 3600, 12h, 30d or 1y)", input));
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<u64>().ok().and_then(|number| number.checked_mul(seconds)) {
+        Some(0) | None => Err(invalid()),
+        Some(lifetime) => Ok(lifetime),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, CryptGuardError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyfile::KeyRole;
+
+    fn signed_packet(lifetime: Option<u64>) -> (KeyFile, Packet) {
+        let key = KeyFile::new(KeyTypes::Falcon512, KeyRole::Public, vec![4; 897]);
+        let mut packet = Packet::new(&key, "release".to_string(), lifetime).unwrap();
+        packet.signature = vec![9; 666];
+        (key, packet)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (key, packet) = signed_packet(Some(3600));
+        let encoded = [packet.to_bytes().as_slice(), b"message"].concat();
+        assert!(Packet::is_packet(&encoded));
+
+        let mut reader = encoded.as_slice();
+        let decoded = Packet::read_from(&mut reader).unwrap();
+        assert_eq!(reader, b"message");
+        assert_eq!((decoded.key_type, decoded.hash, decoded.created, decoded.expires), (packet.key_type, packet.hash, packet.created, packet.expires));
+        assert_eq!((decoded.fingerprint, &decoded.context, &decoded.signature), (packet.fingerprint, &packet.context, &packet.signature));
+        assert_eq!(decoded.message(b"digest"), packet.message(b"digest"));
+        decoded.check_signer(&key).unwrap();
+
+        let other = KeyFile::new(KeyTypes::Falcon512, KeyRole::Public, vec![5; 897]);
+        assert!(matches!(decoded.check_signer(&other), Err(CryptGuardError::SignatureError(_))));
+    }
+
+    #[test]
+    fn message_covers_the_metadata() {
+        let (_, mut packet) = signed_packet(None);
+        let message = packet.message(b"digest");
+        packet.context = "backup".to_string();
+        assert_ne!(packet.message(b"digest"), message);
+    }
+
+    #[test]
+    fn checks_expiry() {
+        let (_, mut packet) = signed_packet(None);
+        packet.check_expiry().unwrap();
+        packet.expires = Some(now() + 60);
+        packet.check_expiry().unwrap();
+        packet.expires = Some(now() - 1);
+        assert!(matches!(packet.check_expiry(), Err(CryptGuardError::SignatureError(_))));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let encoded = signed_packet(None).1.to_bytes();
+        for len in [0, MAGIC.len(), MAGIC.len() + 10, encoded.len() - 1] {
+            assert!(Packet::read_from(&mut &encoded[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_signature() {
+        let (_, packet) = signed_packet(None);
+        let mut encoded = packet.signed_fields();
+        encoded.extend_from_slice(&(MAX_SIGNATURE_LEN as u32 + 1).to_be_bytes());
+        assert!(matches!(Packet::read_from(&mut encoded.as_slice()), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn parses_lifetimes() {
+        assert_eq!(parse_lifetime("3600").unwrap(), 3600);
+        assert_eq!(parse_lifetime("12h").unwrap(), 12 * 3600);
+        assert_eq!(parse_lifetime("2w").unwrap(), 14 * 86400);
+        for input in ["", "0", "h", "5x", "-1d", "99999999999999999999y"] {
+            assert!(parse_lifetime(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951_782_400 + 3661), "2000-02-29 01:01:01 UTC");
+    }
+}
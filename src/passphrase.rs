@@ -0,0 +1,127 @@
+use std::{env, fs::File, io::{BufRead, BufReader, Read}, path::PathBuf};
+use clap::ArgMatches;
+use crate::CryptGuardError;
+
+/// Where a passphrase is read from.
+#[derive(Debug)]
+pub enum Source {
+    /// Given directly on the command line with `-p`; visible in shell history and the process list.
+    Arg(String),
+    /// A file whose first line is the passphrase.
+    File(PathBuf),
+    /// Name of an environment variable holding the passphrase.
+    Env(String),
+    /// An inherited file descriptor; the first line is the passphrase.
+    Fd(i32),
+    /// Interactive prompt on the controlling terminal, without echo.
+    Prompt,
+}
+
+impl Source {
+    /// Source for unlocking a protected secret key, from `--key-passphrase-env`/`--key-passphrase-fd`.
+    pub fn for_key(matches: &ArgMatches) -> Self {
+        from_args(matches, "key-passphrase-env", "key-passphrase-fd")
+    }
+
+    /// Source for the data passphrase of `encrypt`/`decrypt`, or `None` if no option asked for one.
+    pub fn for_data(matches: &ArgMatches) -> Option<Self> {
+        if let Some(passphrase) = matches.get_one::<String>("passphrase") {
+            Some(Source::Arg(passphrase.clone()))
+        } else if let Some(path) = matches.get_one::<PathBuf>("passphrase-file") {
+            Some(Source::File(path.clone()))
+        } else {
+            match from_args(matches, "passphrase-env", "passphrase-fd") {
+                Source::Prompt => None,
+                source => Some(source),
+            }
+        }
+    }
+}
+
+/// Picks the env or fd source if the corresponding argument was given, and the prompt otherwise.
+pub fn from_args(matches: &ArgMatches, env_arg: &str, fd_arg: &str) -> Source {
+    if let Some(var) = matches.get_one::<String>(env_arg) {
+        Source::Env(var.clone())
+    } else if let Some(fd) = matches.get_one::<i32>(fd_arg) {
+        Source::Fd(*fd)
+    } else {
+        Source::Prompt
+    }
+}
+
+/// Reads a passphrase. `confirm` asks twice when prompting, for passphrases that are being set.
+pub fn read(source: &Source, prompt: &str, confirm: bool) -> Result<String, CryptGuardError> {
+    let passphrase = match source {
+        Source::Arg(passphrase) => passphrase.clone(),
+        Source::File(path) => read_first_line(File::open(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?)?,
+        Source::Env(var) => env::var(var)
+            .map_err(|_| CryptGuardError::ParseError(format!("Environment variable {} is not set or not valid UTF-8", var)))?,
+        Source::Fd(fd) => read_fd(*fd)?,
+        Source::Prompt => {
+            let passphrase = rpassword::prompt_password(format!("{}: ", prompt))?;
+            if confirm && rpassword::prompt_password(format!("Repeat {}: ", prompt.to_lowercase()))? != passphrase {
+                return Err(CryptGuardError::ParseError("Passphrases do not match".to_string()));
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err(CryptGuardError::ParseError("Empty passphrase".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Reads the first line of `reader`, without the line terminator.
+pub fn read_first_line<R: Read>(reader: R) -> Result<String, CryptGuardError> {
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<String, CryptGuardError> {
+    use std::{fs::File, io, os::unix::io::BorrowedFd};
+    if fd < 0 {
+        return Err(CryptGuardError::ParseError(format!("Invalid file descriptor: {}", fd)));
+    }
+    // BorrowedFd requires an open descriptor, so check before wrapping it.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        let e = io::Error::last_os_error();
+        return Err(CryptGuardError::IoError(io::Error::new(e.kind(), format!("File descriptor {}: {}", fd, e))));
+    }
+    // Read through a duplicate so the caller's descriptor, possibly stdin, stays open.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    read_first_line(File::from(fd.try_clone_to_owned()?))
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<String, CryptGuardError> {
+    Err(CryptGuardError::ParseError("Reading a passphrase from a file descriptor is only supported on Unix".to_string()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_first_line_and_leaves_fd_open() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let line = b"secret\nrest\n";
+        assert_eq!(unsafe { libc::write(fds[1], line.as_ptr().cast(), line.len()) }, line.len() as isize);
+        unsafe { libc::close(fds[1]) };
+
+        assert_eq!(read_fd(fds[0]).unwrap(), "secret");
+        assert_ne!(unsafe { libc::fcntl(fds[0], libc::F_GETFD) }, -1);
+        unsafe { libc::close(fds[0]) };
+    }
+
+    #[test]
+    fn rejects_closed_fd() {
+        // Far above any descriptor limit, so it can't be open (or opened by a parallel test).
+        assert!(matches!(read_fd(i32::MAX), Err(CryptGuardError::IoError(_))));
+        assert!(matches!(read_fd(-1), Err(CryptGuardError::ParseError(_))));
+    }
+}
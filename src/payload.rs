@@ -0,0 +1,196 @@
+use aead::{generic_array::GenericArray, Aead, KeyInit};
+use aes::Aes256;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20::XChaCha20;
+use chacha20poly1305::XChaCha20Poly1305;
+use cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha3::{Digest, Sha3_256};
+use ::crypt_guard::error::CryptError;
+use crate::{CryptGuardError, SymmetricAlgorithm};
+
+type Aes256CbcEnc = cbc::Encryptor<Aes256>;
+type Aes256CbcDec = cbc::Decryptor<Aes256>;
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// HMAC-SHA3-256 tag appended by the algorithms without built-in authentication.
+const MAC_LEN: usize = 32;
+
+/// Length of the random nonce (or IV) stored in the container header for `algorithm`.
+pub fn nonce_len(algorithm: &SymmetricAlgorithm) -> usize {
+    match algorithm {
+        SymmetricAlgorithm::AES | SymmetricAlgorithm::AES_CTR => 16,
+        SymmetricAlgorithm::AES_GCM_SIV => 12,
+        SymmetricAlgorithm::XChaCha20 | SymmetricAlgorithm::XChaCha20Poly1305 => 24,
+    }
+}
+
+/// Derives a 32 byte key for `purpose` from the Kyber shared secret and the (stretched) passphrase.
+fn derive_key(purpose: &[u8], algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"crypt_guard payload ");
+    hasher.update(purpose);
+    hasher.update(b" v1");
+    hasher.update([algorithm.id()]);
+    hasher.update((shared_secret.len() as u32).to_be_bytes());
+    hasher.update(shared_secret);
+    hasher.update((passphrase.len() as u32).to_be_bytes());
+    hasher.update(passphrase.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Encrypts `data` in one piece, returning `(ciphertext, nonce)`.
+pub fn encrypt(algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str, data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), CryptGuardError> {
+    let key = derive_key(b"key", algorithm, shared_secret, passphrase);
+    let mut nonce = vec![0u8; nonce_len(algorithm)];
+    OsRng.fill_bytes(&mut nonce);
+
+    let encrypted = match algorithm {
+        SymmetricAlgorithm::AES => {
+            let ciphertext = Aes256CbcEnc::new(&key.into(), GenericArray::from_slice(&nonce)).encrypt_padded_vec_mut::<Pkcs7>(data);
+            append_mac(algorithm, shared_secret, passphrase, &nonce, ciphertext)
+        },
+        SymmetricAlgorithm::AES_CTR => {
+            let mut ciphertext = data.to_vec();
+            Aes256Ctr::new(&key.into(), GenericArray::from_slice(&nonce)).apply_keystream(&mut ciphertext);
+            append_mac(algorithm, shared_secret, passphrase, &nonce, ciphertext)
+        },
+        SymmetricAlgorithm::XChaCha20 => {
+            let mut ciphertext = data.to_vec();
+            XChaCha20::new(&key.into(), GenericArray::from_slice(&nonce)).apply_keystream(&mut ciphertext);
+            append_mac(algorithm, shared_secret, passphrase, &nonce, ciphertext)
+        },
+        SymmetricAlgorithm::AES_GCM_SIV => Aes256GcmSiv::new(&key.into())
+            .encrypt(GenericArray::from_slice(&nonce), data)
+            .map_err(|_| crypt_error("Encryption failed"))?,
+        SymmetricAlgorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new(&key.into())
+            .encrypt(GenericArray::from_slice(&nonce), data)
+            .map_err(|_| crypt_error("Encryption failed"))?,
+    };
+    Ok((encrypted, nonce))
+}
+
+/// Reverses `encrypt`, failing if the ciphertext, the nonce, the key or the passphrase don't match.
+pub fn decrypt(algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str, nonce: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptGuardError> {
+    if nonce.len() != nonce_len(algorithm) {
+        return Err(CryptGuardError::ParseError(format!("Invalid nonce: expected {} bytes for {}, got {}", nonce_len(algorithm), algorithm, nonce.len())));
+    }
+    let key = derive_key(b"key", algorithm, shared_secret, passphrase);
+
+    match algorithm {
+        SymmetricAlgorithm::AES => {
+            let ciphertext = check_mac(algorithm, shared_secret, passphrase, nonce, data)?;
+            Aes256CbcDec::new(&key.into(), GenericArray::from_slice(nonce))
+                .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+                .map_err(|_| auth_failure())
+        },
+        SymmetricAlgorithm::AES_CTR => {
+            let mut plaintext = check_mac(algorithm, shared_secret, passphrase, nonce, data)?.to_vec();
+            Aes256Ctr::new(&key.into(), GenericArray::from_slice(nonce)).apply_keystream(&mut plaintext);
+            Ok(plaintext)
+        },
+        SymmetricAlgorithm::XChaCha20 => {
+            let mut plaintext = check_mac(algorithm, shared_secret, passphrase, nonce, data)?.to_vec();
+            XChaCha20::new(&key.into(), GenericArray::from_slice(nonce)).apply_keystream(&mut plaintext);
+            Ok(plaintext)
+        },
+        SymmetricAlgorithm::AES_GCM_SIV => Aes256GcmSiv::new(&key.into())
+            .decrypt(GenericArray::from_slice(nonce), data)
+            .map_err(|_| auth_failure()),
+        SymmetricAlgorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new(&key.into())
+            .decrypt(GenericArray::from_slice(nonce), data)
+            .map_err(|_| auth_failure()),
+    }
+}
+
+/// Encrypt-then-MAC: the tag covers the nonce and the ciphertext.
+fn mac(algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str, nonce: &[u8], ciphertext: &[u8]) -> HmacSha3 {
+    let key = derive_key(b"mac key", algorithm, shared_secret, passphrase);
+    let mut mac = <HmacSha3 as Mac>::new_from_slice(&key).expect("HMAC accepts any key length");
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac
+}
+
+fn append_mac(algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str, nonce: &[u8], mut ciphertext: Vec<u8>) -> Vec<u8> {
+    let tag = mac(algorithm, shared_secret, passphrase, nonce, &ciphertext).finalize().into_bytes();
+    ciphertext.extend_from_slice(&tag);
+    ciphertext
+}
+
+/// Verifies the trailing tag in constant time and returns the ciphertext in front of it.
+fn check_mac<'a>(algorithm: &SymmetricAlgorithm, shared_secret: &[u8], passphrase: &str, nonce: &[u8], data: &'a [u8]) -> Result<&'a [u8], CryptGuardError> {
+    let split = data.len().checked_sub(MAC_LEN).ok_or_else(auth_failure)?;
+    let (ciphertext, tag) = data.split_at(split);
+    mac(algorithm, shared_secret, passphrase, nonce, ciphertext)
+        .verify_slice(tag)
+        .map_err(|_| auth_failure())?;
+    Ok(ciphertext)
+}
+
+fn crypt_error(message: &str) -> CryptGuardError {
+    CryptGuardError::CryptError(CryptError::new(message))
+}
+
+fn auth_failure() -> CryptGuardError {
+    CryptGuardError::AuthError("Decryption failed, wrong key or passphrase or corrupted data".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [3; 32];
+    const PASSPHRASE: &str = "passphrase";
+
+    fn algorithms() -> [SymmetricAlgorithm; 5] {
+        [
+            SymmetricAlgorithm::AES,
+            SymmetricAlgorithm::AES_GCM_SIV,
+            SymmetricAlgorithm::AES_CTR,
+            SymmetricAlgorithm::XChaCha20,
+            SymmetricAlgorithm::XChaCha20Poly1305,
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for algorithm in algorithms() {
+            for data in [&b""[..], b"secret", &[9u8; 1000]] {
+                let (ciphertext, nonce) = encrypt(&algorithm, &SECRET, PASSPHRASE, data).unwrap();
+                assert_eq!(nonce.len(), nonce_len(&algorithm));
+                assert_eq!(decrypt(&algorithm, &SECRET, PASSPHRASE, &nonce, &ciphertext).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn detects_tampering() {
+        for algorithm in algorithms() {
+            let (mut ciphertext, nonce) = encrypt(&algorithm, &SECRET, PASSPHRASE, b"secret").unwrap();
+            ciphertext[0] ^= 1;
+            assert!(matches!(decrypt(&algorithm, &SECRET, PASSPHRASE, &nonce, &ciphertext), Err(CryptGuardError::AuthError(_))));
+        }
+    }
+
+    #[test]
+    fn detects_wrong_passphrase_and_nonce() {
+        for algorithm in algorithms() {
+            let (ciphertext, mut nonce) = encrypt(&algorithm, &SECRET, PASSPHRASE, b"secret").unwrap();
+            assert!(matches!(decrypt(&algorithm, &SECRET, "wrong", &nonce, &ciphertext), Err(CryptGuardError::AuthError(_))));
+            nonce[0] ^= 1;
+            assert!(matches!(decrypt(&algorithm, &SECRET, PASSPHRASE, &nonce, &ciphertext), Err(CryptGuardError::AuthError(_))));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_ciphertext() {
+        for algorithm in algorithms() {
+            let (ciphertext, nonce) = encrypt(&algorithm, &SECRET, PASSPHRASE, b"secret").unwrap();
+            assert!(decrypt(&algorithm, &SECRET, PASSPHRASE, &nonce, &ciphertext[..5]).is_err());
+            assert!(matches!(decrypt(&algorithm, &SECRET, PASSPHRASE, &nonce[1..], &ciphertext), Err(CryptGuardError::ParseError(_))));
+        }
+    }
+}
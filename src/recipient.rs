@@ -0,0 +1,144 @@
+use std::io::Read;
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, XChaCha20Poly1305, XNonce};
+use rand::{rngs::OsRng, RngCore};
+use sha3::{Digest, Sha3_256};
+use ::crypt_guard::error::CryptError;
+use crate::{container::{read_u16, read_u32, read_u8, read_vec, MAX_CIPHER_LEN}, kem::{self, Kem}, CryptGuardError};
+
+const WRAP_NONCE_LEN: usize = 24;
+
+/// A fresh random data-encryption key.
+pub fn generate_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// One copy of the data-encryption key, wrapped to a single Kyber, hybrid or ML-KEM public key.
+#[derive(Debug, Clone)]
+pub struct Stanza {
+    pub key_size: usize,
+    /// The KEM that produced `cipher`.
+    pub kem: Kem,
+    /// KEM ciphertext of the shared secret the wrapping key is derived from.
+    pub cipher: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl Stanza {
+    /// Encapsulates to `public_key` and wraps `data_key` under the resulting shared secret.
+    pub fn wrap(data_key: &[u8; 32], key_size: usize, kem: Kem, public_key: &[u8]) -> Result<Self, CryptGuardError> {
+        let (shared_secret, cipher) = kem::encapsulate_with(kem, key_size, public_key)?;
+        let mut nonce = vec![0u8; WRAP_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let wrapped_key = XChaCha20Poly1305::new(&wrapping_key(&shared_secret).into())
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: data_key, aad: &cipher })
+            .map_err(|_| CryptGuardError::CryptError(CryptError::new("Wrapping the data key failed")))?;
+
+        Ok(Stanza { key_size, kem, cipher, nonce, wrapped_key })
+    }
+
+    /// Recovers the data-encryption key, or `None` if this stanza was wrapped to a different key.
+    pub fn unwrap(&self, secret_key: &[u8]) -> Option<[u8; 32]> {
+        // Kyber decapsulation with the wrong secret key yields an unrelated shared secret,
+        // so a mismatch only shows up as an authentication failure of the wrapped key.
+        let shared_secret = kem::decapsulate_with(self.kem, self.key_size, secret_key, &self.cipher).ok()?;
+        let data_key = XChaCha20Poly1305::new(&wrapping_key(&shared_secret).into())
+            .decrypt(XNonce::from_slice(&self.nonce), Payload { msg: &self.wrapped_key, aad: &self.cipher })
+            .ok()?;
+        data_key.try_into().ok()
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.key_size as u16).to_be_bytes());
+        buf.push(self.kem.id());
+        buf.extend_from_slice(&(self.cipher.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&self.cipher);
+        buf.extend_from_slice(&self.nonce);
+        buf.push(self.wrapped_key.len() as u8);
+        buf.extend_from_slice(&self.wrapped_key);
+    }
+
+    /// Parses a stanza of a container with the given format version; the KEM byte was added in version 5.
+    pub fn decode<R: Read>(reader: &mut R, version: u8) -> Result<Self, CryptGuardError> {
+        let key_size = read_u16(reader)? as usize;
+        let kem_id = if version < 5 { 0 } else { read_u8(reader)? };
+        let kem = Kem::from_id(kem_id)
+            .ok_or_else(|| CryptGuardError::ParseError(format!("Invalid recipient KEM identifier: {}", kem_id)))?;
+        let cipher_len = read_u32(reader)? as usize;
+        if cipher_len > MAX_CIPHER_LEN {
+            return Err(CryptGuardError::ParseError(format!("Recipient stanza is corrupted: a {} byte KEM ciphertext is implausible", cipher_len)));
+        }
+        let cipher = read_vec(reader, cipher_len)?;
+        let nonce = read_vec(reader, WRAP_NONCE_LEN)?;
+        let wrapped_len = read_u8(reader)? as usize;
+        let wrapped_key = read_vec(reader, wrapped_len)?;
+        Ok(Stanza { key_size, kem, cipher, nonce, wrapped_key })
+    }
+}
+
+fn wrapping_key(shared_secret: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"crypt_guard recipient wrap v1");
+    hasher.update(shared_secret);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::VERSION;
+    use ::crypt_guard::*;
+
+    #[test]
+    fn wrap_round_trip() {
+        let (public, secret) = KyberKeypair!(768);
+        let (other_public, other) = KyberKeypair!(768);
+        let (hybrid_public, hybrid_secret) = kem::hybrid_keypair(public.clone(), secret.clone());
+        let (_, hybrid_other) = kem::hybrid_keypair(other_public, other.clone());
+        let (ml_kem_public, ml_kem_secret) = kem::ml_kem_keypair(768).unwrap();
+        let (_, ml_kem_other) = kem::ml_kem_keypair(768).unwrap();
+        let data_key = generate_key();
+
+        for (kem, public, secret, other) in [
+            (Kem::Kyber, &public, &secret, &other),
+            (Kem::X25519Kyber, &hybrid_public, &hybrid_secret, &hybrid_other),
+            (Kem::MlKem, &ml_kem_public, &ml_kem_secret, &ml_kem_other),
+        ] {
+            let mut encoded = Vec::new();
+            Stanza::wrap(&data_key, 768, kem, public).unwrap().encode(&mut encoded);
+            let stanza = Stanza::decode(&mut encoded.as_slice(), VERSION).unwrap();
+
+            assert_eq!(stanza.kem, kem);
+            assert_eq!(stanza.unwrap(secret), Some(data_key));
+            assert_eq!(stanza.unwrap(other), None);
+        }
+    }
+
+    #[test]
+    fn decodes_stanzas_without_kem_byte() {
+        let (public, secret) = KyberKeypair!(512);
+        let data_key = generate_key();
+        let mut encoded = Vec::new();
+        Stanza::wrap(&data_key, 512, Kem::Kyber, &public).unwrap().encode(&mut encoded);
+        encoded.remove(2);
+
+        let stanza = Stanza::decode(&mut encoded.as_slice(), 4).unwrap();
+        assert_eq!(stanza.kem, Kem::Kyber);
+        assert_eq!(stanza.unwrap(&secret), Some(data_key));
+    }
+
+    #[test]
+    fn rejects_unknown_kem_and_oversized_cipher() {
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&768u16.to_be_bytes());
+        encoded.push(7);
+        assert!(matches!(Stanza::decode(&mut encoded.as_slice(), VERSION), Err(CryptGuardError::ParseError(_))));
+
+        encoded[2] = 0;
+        encoded.extend_from_slice(&(MAX_CIPHER_LEN as u32 + 1).to_be_bytes());
+        assert!(matches!(Stanza::decode(&mut encoded.as_slice(), VERSION), Err(CryptGuardError::ParseError(_))));
+    }
+}
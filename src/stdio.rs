@@ -0,0 +1,166 @@
+use std::{fs::{self, File}, io::{self, BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+use rand::{rngs::OsRng, RngCore};
+
+/// The path that stands for stdin or stdout.
+pub const STDIO: &str = "-";
+
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO
+}
+
+/// Opens `path` for reading, or stdin for `-`.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        Ok(Box::new(BufReader::new(io::stdin().lock())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Reads all of `path`, or all of stdin for `-`.
+pub fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_input(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Creates `path` (and its parent directories) for writing, or returns stdout for `-`.
+pub fn create_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        return Ok(Box::new(BufWriter::new(io::stdout().lock())));
+    }
+
+    // Create the parent directory if it does not exist
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+/// Writes `data` to `path`, or to stdout for `-`.
+pub fn write_output(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut output = create_output(path)?;
+    output.write_all(data)?;
+    output.flush()
+}
+
+/// Human readable name for progress messages.
+pub fn display(path: &Path, stdio_name: &str) -> String {
+    if is_stdio(path) {
+        stdio_name.to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// A file written next to its target and renamed over it by `persist`, so a failed or interrupted
+/// write never leaves a truncated file behind. The temporary file is removed if it isn't persisted.
+pub struct AtomicFile {
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file in the directory of `path`, readable only by the owner if `private`.
+    pub fn create(path: &Path, private: bool) -> io::Result<Self> {
+        let name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display())))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(parent)?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        loop {
+            let temp_path = parent.join(format!(".{}.{:016x}.tmp", name.to_string_lossy(), OsRng.next_u64()));
+            match options.open(&temp_path) {
+                Ok(file) => return Ok(AtomicFile { file, temp_path, path: path.to_path_buf(), persisted: false }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Syncs the data to disk and renames the temporary file over the target.
+    pub fn persist(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crypt_guard-stdio-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn persist_replaces_target() {
+        let dir = scratch("persist");
+        let path = dir.join("key.sec");
+        fs::write(&path, b"old").unwrap();
+
+        let mut file = AtomicFile::create(&path, true).unwrap();
+        file.write_all(b"new").unwrap();
+        file.persist().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropping_keeps_target() {
+        let dir = scratch("drop");
+        let path = dir.join("key.sec");
+        fs::write(&path, b"old").unwrap();
+
+        let mut file = AtomicFile::create(&path, true).unwrap();
+        file.write_all(b"partial").unwrap();
+        drop(file);
+
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
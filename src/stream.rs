@@ -0,0 +1,313 @@
+use std::io::{Read, Write};
+use std::ops::Sub;
+use aead::{
+    generic_array::{typenum::{Unsigned, U5}, ArrayLength, GenericArray},
+    stream::{DecryptorBE32, EncryptorBE32},
+    AeadCore, AeadInPlace, KeyInit, Payload,
+};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::XChaCha20Poly1305;
+use rand::{rngs::OsRng, RngCore};
+use sha3::{Digest, Sha3_256};
+use ::crypt_guard::error::CryptError;
+use crate::{CryptGuardError, SymmetricAlgorithm};
+
+/// Upper bound on `--chunk-size` and on the chunk size read from a container header, so a
+/// damaged or hostile header can't make us allocate gigabytes for the chunk buffer.
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Poly1305 and POLYVAL tags are both 16 bytes.
+const TAG_LEN: usize = 16;
+
+/// STREAM reserves 4 bytes of the nonce for the chunk counter and 1 for the final-chunk flag.
+const NONCE_OVERHEAD: usize = 5;
+
+/// Only the AEAD algorithms can be used in chunked mode.
+pub fn supports(algorithm: &SymmetricAlgorithm) -> bool {
+    matches!(algorithm, SymmetricAlgorithm::XChaCha20Poly1305 | SymmetricAlgorithm::AES_GCM_SIV)
+}
+
+/// Random nonce prefix of the right length for the algorithm.
+pub fn nonce_prefix(algorithm: &SymmetricAlgorithm) -> Result<Vec<u8>, CryptGuardError> {
+    let len = match algorithm {
+        SymmetricAlgorithm::XChaCha20Poly1305 => <XChaCha20Poly1305 as AeadCore>::NonceSize::USIZE,
+        SymmetricAlgorithm::AES_GCM_SIV => <Aes256GcmSiv as AeadCore>::NonceSize::USIZE,
+        _ => return Err(unsupported(algorithm)),
+    } - NONCE_OVERHEAD;
+
+    let mut prefix = vec![0u8; len];
+    OsRng.fill_bytes(&mut prefix);
+    Ok(prefix)
+}
+
+/// Derives the chunk key from the Kyber shared secret and the (stretched) passphrase.
+pub fn derive_key(shared_secret: &[u8], passphrase: &str) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"crypt_guard stream key v1");
+    hasher.update((shared_secret.len() as u32).to_be_bytes());
+    hasher.update(shared_secret);
+    hasher.update((passphrase.len() as u32).to_be_bytes());
+    hasher.update(passphrase.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Encrypts `reader` into `writer` chunk by chunk, binding every chunk to `aad`.
+/// Returns the number of plaintext bytes processed.
+pub fn encrypt<R: Read, W: Write>(
+    algorithm: &SymmetricAlgorithm,
+    key: &[u8; 32],
+    prefix: &[u8],
+    aad: &[u8],
+    chunk_size: usize,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, CryptGuardError> {
+    let key = GenericArray::from_slice(&key[..]);
+    match algorithm {
+        SymmetricAlgorithm::XChaCha20Poly1305 => encrypt_chunks(XChaCha20Poly1305::new(key), prefix, aad, chunk_size, reader, writer),
+        SymmetricAlgorithm::AES_GCM_SIV => encrypt_chunks(Aes256GcmSiv::new(key), prefix, aad, chunk_size, reader, writer),
+        _ => Err(unsupported(algorithm)),
+    }
+}
+
+/// Decrypts a chunked payload, failing on tampered, reordered or truncated chunks.
+/// Returns the number of plaintext bytes written.
+pub fn decrypt<R: Read, W: Write>(
+    algorithm: &SymmetricAlgorithm,
+    key: &[u8; 32],
+    prefix: &[u8],
+    aad: &[u8],
+    chunk_size: usize,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, CryptGuardError> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE as usize {
+        return Err(CryptGuardError::ParseError(format!("Container is corrupted: a chunk size of {} bytes is implausible", chunk_size)));
+    }
+    let key = GenericArray::from_slice(&key[..]);
+    match algorithm {
+        SymmetricAlgorithm::XChaCha20Poly1305 => decrypt_chunks(XChaCha20Poly1305::new(key), prefix, aad, chunk_size, reader, writer),
+        SymmetricAlgorithm::AES_GCM_SIV => decrypt_chunks(Aes256GcmSiv::new(key), prefix, aad, chunk_size, reader, writer),
+        _ => Err(unsupported(algorithm)),
+    }
+}
+
+fn encrypt_chunks<A, R, W>(cipher: A, prefix: &[u8], aad: &[u8], chunk_size: usize, reader: &mut R, writer: &mut W) -> Result<u64, CryptGuardError>
+where
+    A: AeadInPlace + KeyInit,
+    R: Read,
+    W: Write,
+    A::NonceSize: Sub<U5>,
+    <<A as AeadCore>::NonceSize as Sub<U5>>::Output: ArrayLength<u8>,
+{
+    check_prefix::<A>(prefix)?;
+    let mut encryptor = EncryptorBE32::from_aead(cipher, GenericArray::from_slice(prefix));
+    // One byte of look-ahead tells us whether the current chunk is the last one.
+    let mut buf = vec![0u8; chunk_size + 1];
+    let mut filled = read_full(reader, &mut buf)?;
+    let mut total = 0u64;
+
+    loop {
+        if filled <= chunk_size {
+            let chunk = encryptor
+                .encrypt_last(Payload { msg: &buf[..filled], aad })
+                .map_err(|_| crypt_error("Chunk encryption failed"))?;
+            writer.write_all(&chunk)?;
+            total += filled as u64;
+            break;
+        }
+
+        let chunk = encryptor
+            .encrypt_next(Payload { msg: &buf[..chunk_size], aad })
+            .map_err(|_| crypt_error("Chunk encryption failed"))?;
+        writer.write_all(&chunk)?;
+        total += chunk_size as u64;
+
+        buf.copy_within(chunk_size..filled, 0);
+        filled -= chunk_size;
+        filled += read_full(reader, &mut buf[filled..])?;
+    }
+
+    writer.flush()?;
+    Ok(total)
+}
+
+fn decrypt_chunks<A, R, W>(cipher: A, prefix: &[u8], aad: &[u8], chunk_size: usize, reader: &mut R, writer: &mut W) -> Result<u64, CryptGuardError>
+where
+    A: AeadInPlace + KeyInit,
+    R: Read,
+    W: Write,
+    A::NonceSize: Sub<U5>,
+    <<A as AeadCore>::NonceSize as Sub<U5>>::Output: ArrayLength<u8>,
+{
+    check_prefix::<A>(prefix)?;
+    let mut decryptor = DecryptorBE32::from_aead(cipher, GenericArray::from_slice(prefix));
+    let sealed_size = chunk_size + TAG_LEN;
+    let mut buf = vec![0u8; sealed_size + 1];
+    let mut filled = read_full(reader, &mut buf)?;
+    let mut total = 0u64;
+
+    loop {
+        if filled <= sealed_size {
+            // A stream cut at a chunk boundary ends on a chunk sealed as non-final, so this fails.
+            let chunk = decryptor
+                .decrypt_last(Payload { msg: &buf[..filled], aad })
+                .map_err(|_| CryptGuardError::AuthError("Final chunk failed authentication; wrong key or passphrase, or the data is corrupted or truncated".to_string()))?;
+            writer.write_all(&chunk)?;
+            total += chunk.len() as u64;
+            break;
+        }
+
+        let chunk = decryptor
+            .decrypt_next(Payload { msg: &buf[..sealed_size], aad })
+            .map_err(|_| CryptGuardError::AuthError("Chunk failed authentication; wrong key or passphrase, or the data is corrupted or reordered".to_string()))?;
+        writer.write_all(&chunk)?;
+        total += chunk.len() as u64;
+
+        buf.copy_within(sealed_size..filled, 0);
+        filled -= sealed_size;
+        filled += read_full(reader, &mut buf[filled..])?;
+    }
+
+    writer.flush()?;
+    Ok(total)
+}
+
+/// The prefix comes from the container header, so a wrong length is a parse error rather than
+/// a panic in `GenericArray::from_slice`.
+fn check_prefix<A: AeadCore>(prefix: &[u8]) -> Result<(), CryptGuardError> {
+    let expected = A::NonceSize::USIZE - NONCE_OVERHEAD;
+    if prefix.len() != expected {
+        return Err(CryptGuardError::ParseError(format!("Invalid STREAM nonce prefix: expected {} bytes, got {}", expected, prefix.len())));
+    }
+    Ok(())
+}
+
+/// Reads until `buf` is full or the reader is exhausted.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, CryptGuardError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+fn crypt_error(message: &str) -> CryptGuardError {
+    CryptGuardError::CryptError(CryptError::new(message))
+}
+
+fn unsupported(algorithm: &SymmetricAlgorithm) -> CryptGuardError {
+    CryptGuardError::ParseError(format!("Chunked mode is only available for xchacha20poly1305 and aes_gcm_siv, not {}", algorithm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+    const AAD: &[u8] = b"header";
+    const CHUNK_SIZE: usize = 16;
+    const SEALED_SIZE: usize = CHUNK_SIZE + TAG_LEN;
+
+    /// Three full chunks and a short final one.
+    fn plaintext() -> Vec<u8> {
+        (0..CHUNK_SIZE as u8 * 3 + 5).collect()
+    }
+
+    fn sealed(algorithm: &SymmetricAlgorithm, prefix: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::new();
+        encrypt(algorithm, &KEY, prefix, AAD, CHUNK_SIZE, &mut plaintext().as_slice(), &mut sealed).unwrap();
+        sealed
+    }
+
+    fn open(algorithm: &SymmetricAlgorithm, prefix: &[u8], sealed: &[u8]) -> Result<Vec<u8>, CryptGuardError> {
+        let mut plaintext = Vec::new();
+        decrypt(algorithm, &KEY, prefix, AAD, CHUNK_SIZE, &mut &sealed[..], &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn algorithms() -> [SymmetricAlgorithm; 2] {
+        [SymmetricAlgorithm::XChaCha20Poly1305, SymmetricAlgorithm::AES_GCM_SIV]
+    }
+
+    #[test]
+    fn round_trip() {
+        for algorithm in algorithms() {
+            let prefix = nonce_prefix(&algorithm).unwrap();
+            assert_eq!(open(&algorithm, &prefix, &sealed(&algorithm, &prefix)).unwrap(), plaintext());
+        }
+    }
+
+    #[test]
+    fn empty_input_is_one_final_chunk() {
+        for algorithm in algorithms() {
+            let prefix = nonce_prefix(&algorithm).unwrap();
+            let mut sealed = Vec::new();
+            encrypt(&algorithm, &KEY, &prefix, AAD, CHUNK_SIZE, &mut &[][..], &mut sealed).unwrap();
+            assert_eq!(sealed.len(), TAG_LEN);
+            assert!(open(&algorithm, &prefix, &sealed).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn detects_truncation_at_chunk_boundary() {
+        for algorithm in algorithms() {
+            let prefix = nonce_prefix(&algorithm).unwrap();
+            let sealed = sealed(&algorithm, &prefix);
+            let truncated = &sealed[..SEALED_SIZE * 3];
+            assert!(matches!(open(&algorithm, &prefix, truncated), Err(CryptGuardError::AuthError(_))));
+        }
+    }
+
+    #[test]
+    fn detects_truncation_inside_chunk() {
+        for algorithm in algorithms() {
+            let prefix = nonce_prefix(&algorithm).unwrap();
+            let sealed = sealed(&algorithm, &prefix);
+            let truncated = &sealed[..sealed.len() - 1];
+            assert!(matches!(open(&algorithm, &prefix, truncated), Err(CryptGuardError::AuthError(_))));
+        }
+    }
+
+    #[test]
+    fn detects_reordered_chunks() {
+        for algorithm in algorithms() {
+            let prefix = nonce_prefix(&algorithm).unwrap();
+            let mut sealed = sealed(&algorithm, &prefix);
+            let (first, rest) = sealed.split_at_mut(SEALED_SIZE);
+            first.swap_with_slice(&mut rest[..SEALED_SIZE]);
+            assert!(matches!(open(&algorithm, &prefix, &sealed), Err(CryptGuardError::AuthError(_))));
+        }
+    }
+
+    #[test]
+    fn detects_wrong_aad() {
+        let algorithm = SymmetricAlgorithm::XChaCha20Poly1305;
+        let prefix = nonce_prefix(&algorithm).unwrap();
+        let sealed = sealed(&algorithm, &prefix);
+        let result = decrypt(&algorithm, &KEY, &prefix, b"other header", CHUNK_SIZE, &mut sealed.as_slice(), &mut Vec::new());
+        assert!(matches!(result, Err(CryptGuardError::AuthError(_))));
+    }
+
+    #[test]
+    fn rejects_wrong_prefix_length() {
+        let algorithm = SymmetricAlgorithm::XChaCha20Poly1305;
+        let prefix = nonce_prefix(&algorithm).unwrap();
+        let sealed = sealed(&algorithm, &prefix);
+        assert!(matches!(open(&algorithm, &prefix[1..], &sealed), Err(CryptGuardError::ParseError(_))));
+    }
+
+    #[test]
+    fn rejects_implausible_chunk_size() {
+        let algorithm = SymmetricAlgorithm::XChaCha20Poly1305;
+        let prefix = nonce_prefix(&algorithm).unwrap();
+        let chunk_size = MAX_CHUNK_SIZE as usize + 1;
+        let result = decrypt(&algorithm, &KEY, &prefix, AAD, chunk_size, &mut &[][..], &mut Vec::new());
+        assert!(matches!(result, Err(CryptGuardError::ParseError(_))));
+    }
+}
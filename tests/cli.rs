@@ -0,0 +1,113 @@
+use std::{env, fs, io::Write, path::{Path, PathBuf}, process::{Command, Output, Stdio}};
+
+const PASSPHRASE: &str = "correct horse battery staple";
+const PLAINTEXT: &[u8] = b"attack at dawn\n\x00\xff binary tail";
+const ALGORITHMS: [&str; 5] = ["aes", "aes_gcm_siv", "aes_ctr", "xchacha20", "xchacha20poly1305"];
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("crypt_guard-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn crypt_guard(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crypt_guard"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Runs the binary with `args`, feeding `stdin`, and returns its stdout after checking it succeeded.
+fn run(args: &[&str], stdin: &[u8]) -> Vec<u8> {
+    let output = crypt_guard(args, stdin);
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/// Generates a Kyber1024 key pair in `dir/keys`, returning `(public, secret)`.
+fn keygen(dir: &Path) -> (String, String) {
+    let keys = dir.join("keys");
+    run(&["keygen", "-a", "kyber1024", "-d", keys.to_str().unwrap()], b"");
+    (keys.join("keys.pub").display().to_string(), keys.join("keys.sec").display().to_string())
+}
+
+#[test]
+fn stdout_pipe_round_trip() {
+    let dir = scratch("pipe");
+    let (public, secret) = keygen(&dir);
+
+    for algorithm in ALGORITHMS {
+        let sealed = run(&["encrypt", "-i", "-", "-o", "-", "-k", &public, "-a", algorithm, "-p", PASSPHRASE], PLAINTEXT);
+        let opened = run(&["decrypt", "-i", "-", "-o", "-", "-k", &secret, "-p", PASSPHRASE], &sealed);
+        assert_eq!(opened, PLAINTEXT, "{}", algorithm);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn wrong_passphrase_is_an_authentication_failure() {
+    let dir = scratch("auth");
+    let (public, secret) = keygen(&dir);
+
+    for algorithm in ALGORITHMS {
+        let sealed = run(&["encrypt", "-i", "-", "-o", "-", "-k", &public, "-a", algorithm, "-p", PASSPHRASE], PLAINTEXT);
+        let output = crypt_guard(&["decrypt", "-i", "-", "-o", "-", "-k", &secret, "-p", "wrong"], &sealed);
+        assert_eq!(output.status.code(), Some(4), "{}", algorithm);
+        assert!(output.stdout.is_empty(), "{}", algorithm);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn verify_signed_never_clobbers_on_failure() {
+    let dir = scratch("verify-signed");
+    let keys = dir.join("signer");
+    run(&["keygen", "-a", "ml-dsa-44", "-d", keys.to_str().unwrap()], b"");
+    let (public, secret) = (keys.join("signer.pub"), keys.join("signer.sec"));
+    let (message, signed, output) = (dir.join("message"), dir.join("message.signed"), dir.join("message.out"));
+    fs::write(&message, PLAINTEXT).unwrap();
+    let path = |path: &Path| path.to_str().unwrap().to_string();
+
+    // A context makes it a signature packet, whose message is copied from the input after verifying
+    run(&["sign", "-t", "signed", "-i", &path(&message), "-o", &path(&signed), "-k", &path(&secret), "--context", "test"], b"");
+    let original = fs::read(&signed).unwrap();
+
+    let in_place = crypt_guard(&["verify", "signed", "-i", &path(&signed), "-o", &path(&signed), "-k", &path(&public), "--context", "test"], b"");
+    assert!(!in_place.status.success());
+    assert_eq!(fs::read(&signed).unwrap(), original);
+
+    // A bad signature leaves an existing output file alone
+    fs::write(&output, b"keep me").unwrap();
+    let mut tampered = original.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    fs::write(&signed, &tampered).unwrap();
+    let failed = crypt_guard(&["verify", "signed", "-i", &path(&signed), "-o", &path(&output), "-k", &path(&public), "--context", "test"], b"");
+    assert!(!failed.status.success());
+    assert_eq!(fs::read(&output).unwrap(), b"keep me");
+
+    fs::write(&signed, &original).unwrap();
+    run(&["verify", "signed", "-i", &path(&signed), "-o", &path(&output), "-k", &path(&public), "--context", "test"], b"");
+    assert_eq!(fs::read(&output).unwrap(), PLAINTEXT);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn message_mode_round_trip() {
+    let dir = scratch("message");
+    let (public, secret) = keygen(&dir);
+
+    for algorithm in ALGORITHMS {
+        let sealed = run(&["encrypt", "-m", "-i", "attack at dawn", "-k", &public, "-a", algorithm, "-p", PASSPHRASE], b"");
+        let sealed = String::from_utf8(sealed).unwrap();
+        let opened = run(&["decrypt", "-m", "-i", sealed.trim_end(), "-k", &secret, "-p", PASSPHRASE], b"");
+        assert_eq!(opened, b"attack at dawn", "{}", algorithm);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}